@@ -1,6 +1,6 @@
-use std::thread;
+use std::collections::HashMap;
 
-use evdev::{uinput::VirtualDevice, EventType, InputEvent, Key};
+use evdev::{EventType, InputEvent, Key};
 use fork::{fork, setsid, Fork};
 use log::debug;
 use log::error;
@@ -8,25 +8,81 @@ use nix::sys::signal;
 use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
 use std::process::{exit, Command, Stdio};
 
+use crate::clock::Clock;
+use crate::device::OutputDevice;
 use crate::event::RelativeEvent;
 use crate::{action::Action, event::KeyEvent};
 
 pub struct ActionDispatcher {
     // Device to emit events
-    device: VirtualDevice,
+    device: Box<dyn OutputDevice>,
+    // Devices to emit events remapped from the devices with devices.options.output
+    outputs: HashMap<String, Box<dyn OutputDevice>>,
+    // The output of the device whose events are handled now. None for `device`.
+    output: Option<String>,
     // Whether we've called a sigaction for spawing commands or not
     sigaction_set: bool,
+    // Keys the output devices have pressed and not released yet, and their outputs
+    pressed_keys: HashMap<Key, Option<String>>,
+    // Sleeps for Action::Delay
+    clock: Box<dyn Clock>,
 }
 
 impl ActionDispatcher {
-    pub fn new(device: VirtualDevice) -> ActionDispatcher {
+    pub fn new(device: Box<dyn OutputDevice>, clock: Box<dyn Clock>) -> ActionDispatcher {
         ActionDispatcher {
             device,
+            outputs: HashMap::new(),
+            output: None,
             sigaction_set: false,
+            pressed_keys: HashMap::new(),
+            clock,
         }
     }
 
-    // Execute Actions created by EventHandler. This should be the only public method of ActionDispatcher.
+    pub fn has_output(&self, name: &str) -> bool {
+        self.outputs.contains_key(name)
+    }
+
+    pub fn add_output(&mut self, name: String, device: Box<dyn OutputDevice>) {
+        self.outputs.insert(name, device);
+    }
+
+    // Drop output devices that devices.options no longer refer to, releasing the keys they've pressed
+    pub fn retain_outputs(&mut self, names: &[&str]) -> std::io::Result<()> {
+        let removed: Vec<String> = self
+            .outputs
+            .keys()
+            .filter(|name| !names.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        for name in removed {
+            let output = Some(name.clone());
+            let keys: Vec<Key> = self
+                .pressed_keys
+                .iter()
+                .filter(|(_, pressed_output)| **pressed_output == output)
+                .map(|(key, _)| *key)
+                .collect();
+            for key in keys {
+                self.pressed_keys.remove(&key);
+                self.emit(&output, &[InputEvent::new_now(EventType::KEY, key.code(), RELEASE)])?;
+            }
+            self.outputs.remove(&name);
+            if self.output == output {
+                self.output = None;
+            }
+        }
+        Ok(())
+    }
+
+    // Emit the following actions through the output of this name, or the default device if None.
+    // An unknown output falls back to the default device.
+    pub fn set_output(&mut self, output: Option<&str>) {
+        self.output = output.filter(|output| self.has_output(output)).map(str::to_string);
+    }
+
+    // Execute Actions created by EventHandler. This is the main interface of ActionDispatcher.
     pub fn on_action(&mut self, action: Action) -> anyhow::Result<()> {
         match action {
             Action::KeyEvent(key_event) => self.on_key_event(key_event)?,
@@ -49,7 +105,7 @@ impl ActionDispatcher {
 
             Action::InputEvent(event) => self.send_event(event)?,
             Action::Command(command) => self.run_command(command),
-            Action::Delay(duration) => thread::sleep(duration),
+            Action::Delay(duration) => self.clock.sleep(duration),
         }
         Ok(())
     }
@@ -74,14 +130,42 @@ impl ActionDispatcher {
                 mouse_movement.value,
             ));
         }
-        self.device.emit(&mousemovementbatch)
+        let output = self.output.clone();
+        self.emit(&output, &mousemovementbatch)
+    }
+
+    // Release every key the output device has pressed, so that nothing stays pressed
+    // when the events that would release them never come.
+    pub fn release_all(&mut self) -> std::io::Result<()> {
+        let keys: Vec<(Key, Option<String>)> = self.pressed_keys.drain().collect();
+        for (key, output) in keys {
+            self.emit(&output, &[InputEvent::new_now(EventType::KEY, key.code(), RELEASE)])?;
+        }
+        Ok(())
     }
 
     fn send_event(&mut self, event: InputEvent) -> std::io::Result<()> {
+        let mut output = self.output.clone();
         if event.event_type() == EventType::KEY {
-            debug!("{}: {:?}", event.value(), Key::new(event.code()))
+            let key = Key::new(event.code());
+            debug!("{}: {:?}", event.value(), key);
+            // A key is repeated and released by the output that pressed it, even through another device's events
+            match event.value() {
+                PRESS => {
+                    self.pressed_keys.insert(key, output.clone());
+                }
+                RELEASE => output = self.pressed_keys.remove(&key).unwrap_or(output),
+                _ => output = self.pressed_keys.get(&key).cloned().unwrap_or(output),
+            };
+        }
+        self.emit(&output, &[event])
+    }
+
+    fn emit(&mut self, output: &Option<String>, events: &[InputEvent]) -> std::io::Result<()> {
+        match output.as_ref().and_then(|output| self.outputs.get_mut(output)) {
+            Some(device) => device.emit(events),
+            None => self.device.emit(events),
         }
-        self.device.emit(&[event])
     }
 
     fn run_command(&mut self, command: Vec<String>) {
@@ -132,3 +216,7 @@ impl ActionDispatcher {
         }
     }
 }
+
+// InputEvent#value
+const RELEASE: i32 = 0;
+const PRESS: i32 = 1;
@@ -0,0 +1,141 @@
+use crate::config::Config;
+use evdev::{EventType, InputEvent, Key};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+// Chords that main checks on input events
+pub struct Chords {
+    // panic_chord
+    pub panic: Chord,
+    // escape_chord
+    pub escape: Chord,
+}
+
+impl Chords {
+    pub fn new(config: &Config) -> Chords {
+        Chords {
+            panic: Chord::new(config.panic_chord.clone(), Duration::ZERO),
+            escape: Chord::new(config.escape_chord.keys.clone(), config.escape_chord.hold),
+        }
+    }
+}
+
+// Detect a set of keys held together on the input devices, before any remapping
+pub struct Chord {
+    keys: Vec<Key>,
+    // How long the keys need to be held together
+    hold: Duration,
+    // Keys of the chord that are currently pressed
+    pressed: HashSet<Key>,
+    // When all the keys got pressed, until the chord triggers
+    completed_at: Option<SystemTime>,
+}
+
+impl Chord {
+    // An empty chord never triggers
+    pub fn new(keys: Vec<Key>, hold: Duration) -> Chord {
+        Chord {
+            keys,
+            hold,
+            pressed: HashSet::new(),
+            completed_at: None,
+        }
+    }
+
+    // Return true once the keys have been held together for `hold`. Since this is checked on events,
+    // a chord with `hold` relies on key repeats while holding the keys.
+    pub fn on_event(&mut self, event: &InputEvent) -> bool {
+        if event.event_type() != EventType::KEY {
+            return false;
+        }
+        let key = Key::new(event.code());
+        if self.keys.contains(&key) {
+            match event.value() {
+                PRESS if self.pressed.insert(key) && self.pressed.len() == self.keys.len() => {
+                    self.completed_at = Some(event.timestamp());
+                }
+                RELEASE => {
+                    self.pressed.remove(&key);
+                    self.completed_at = None;
+                }
+                _ => {}
+            }
+        }
+        match self.completed_at {
+            Some(completed_at) if event.timestamp().duration_since(completed_at).unwrap_or_default() >= self.hold => {
+                // Don't trigger again until the keys are pressed again
+                self.completed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Whether none of the keys is pressed
+    pub fn is_released(&self) -> bool {
+        self.pressed.is_empty()
+    }
+}
+
+// InputEvent#value
+const RELEASE: i32 = 0;
+const PRESS: i32 = 1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::libc::{input_event, timeval};
+
+    const REPEAT: i32 = 2;
+
+    fn key_event(key: Key, value: i32) -> InputEvent {
+        key_event_at(key, value, 0)
+    }
+
+    fn key_event_at(key: Key, value: i32, millis: i64) -> InputEvent {
+        InputEvent::from(input_event {
+            time: timeval {
+                tv_sec: millis / 1000,
+                tv_usec: millis % 1000 * 1000,
+            },
+            type_: EventType::KEY.0,
+            code: key.code(),
+            value,
+        })
+    }
+
+    #[test]
+    fn test_chord() {
+        let mut chord = Chord::new(vec![Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT, Key::KEY_ESC], Duration::ZERO);
+        assert!(!chord.on_event(&key_event(Key::KEY_LEFTSHIFT, PRESS)));
+        assert!(!chord.on_event(&key_event(Key::KEY_A, PRESS)));
+        assert!(!chord.on_event(&key_event(Key::KEY_RIGHTSHIFT, PRESS)));
+        assert!(chord.on_event(&key_event(Key::KEY_ESC, PRESS)));
+        // Repeats don't trigger it again
+        assert!(!chord.on_event(&key_event(Key::KEY_ESC, REPEAT)));
+        assert!(!chord.on_event(&key_event(Key::KEY_ESC, RELEASE)));
+        assert!(chord.on_event(&key_event(Key::KEY_ESC, PRESS)));
+    }
+
+    #[test]
+    fn test_chord_hold() {
+        let mut chord = Chord::new(vec![Key::KEY_LEFTSHIFT, Key::KEY_ESC], Duration::from_millis(3000));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_LEFTSHIFT, PRESS, 1000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, PRESS, 2000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, REPEAT, 4000)));
+        assert!(chord.on_event(&key_event_at(Key::KEY_ESC, REPEAT, 5000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, REPEAT, 6000)));
+
+        // Releasing any key restarts the timer
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, RELEASE, 7000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, PRESS, 8000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_LEFTSHIFT, RELEASE, 9000)));
+        assert!(!chord.on_event(&key_event_at(Key::KEY_ESC, REPEAT, 12000)));
+    }
+
+    #[test]
+    fn test_empty_chord() {
+        let mut chord = Chord::new(vec![], Duration::ZERO);
+        assert!(!chord.on_event(&key_event(Key::KEY_A, PRESS)));
+    }
+}
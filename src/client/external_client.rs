@@ -0,0 +1,324 @@
+use crate::client::{Client, WMClient, WindowProperties};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The focused window reported by an external command or helper.
+// Either a JSON object like {"application": "firefox", "window": "Mozilla Firefox", "pid": 1234},
+// or the application and the window title on separate lines.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct FocusReport {
+    #[serde(default, alias = "app", alias = "app_id", alias = "class")]
+    pub application: Option<String>,
+    #[serde(default, alias = "title")]
+    pub window: Option<String>,
+    #[serde(default)]
+    pub pid: Option<u32>,
+}
+
+impl FocusReport {
+    pub fn parse(output: &str) -> anyhow::Result<FocusReport> {
+        let output = output.trim_end_matches('\n');
+        if output.trim_start().starts_with('{') {
+            return Ok(serde_json::from_str(output)?);
+        }
+        let mut lines = output
+            .lines()
+            .map(|line| Some(line.to_string()).filter(|line| !line.is_empty()));
+        Ok(FocusReport {
+            application: lines.next().flatten(),
+            window: lines.next().flatten(),
+            pid: None,
+        })
+    }
+
+    fn properties(&self) -> WindowProperties {
+        WindowProperties {
+            pid: self.pid,
+            ..Default::default()
+        }
+    }
+}
+
+// --focus-command or --focus-pipe, which replace the client of the cargo feature
+pub fn build_external_client(command: Option<String>, pipe: Option<PathBuf>) -> Option<WMClient> {
+    if let Some(command) = command {
+        return Some(WMClient::new("command", Box::new(CommandClient::new(command))));
+    }
+    pipe.map(|path| WMClient::new("pipe", Box::new(PipeClient::new(path))))
+}
+
+// Run a shell command to ask the focused window.
+// It runs on a background thread while keys are pressed, and key events are served its last focus.
+pub struct CommandClient {
+    command: String,
+    focus: Arc<Mutex<FocusReport>>,
+    // Asks the background thread to run the command again
+    requests: Option<Sender<()>>,
+}
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+const COMMAND_INTERVAL: Duration = Duration::from_millis(100);
+
+impl CommandClient {
+    pub fn new(command: String) -> CommandClient {
+        CommandClient {
+            command,
+            focus: Arc::new(Mutex::new(FocusReport::default())),
+            requests: None,
+        }
+    }
+
+    fn focus(&self) -> FocusReport {
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(());
+        }
+        self.focus.lock().unwrap().clone()
+    }
+}
+
+impl Client for CommandClient {
+    fn supported(&mut self) -> bool {
+        match run_command(&self.command) {
+            Ok(focus) => {
+                *self.focus.lock().unwrap() = focus;
+                if self.requests.is_none() {
+                    self.requests = Some(poll_command(self.command.clone(), self.focus.clone()));
+                }
+                true
+            }
+            Err(e) => {
+                println!("CommandClient: {e}");
+                false
+            }
+        }
+    }
+
+    fn current_window(&mut self) -> Option<String> {
+        self.focus().window
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        self.focus().application
+    }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        self.focus().properties()
+    }
+}
+
+// Wait for a key event, then keep running the command every COMMAND_INTERVAL until key events stop.
+// A failure keeps the last focus, and is reported only once until the command works again.
+fn poll_command(command: String, focus: Arc<Mutex<FocusReport>>) -> Sender<()> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut reported = false;
+        // Stop when the client is dropped
+        while rx.recv().is_ok() {
+            loop {
+                match run_command(&command) {
+                    Ok(report) => {
+                        *focus.lock().unwrap() = report;
+                        reported = false;
+                    }
+                    Err(e) if !reported => {
+                        println!("CommandClient failed, retrying quietly: {e}");
+                        reported = true;
+                    }
+                    Err(_) => {}
+                }
+                thread::sleep(COMMAND_INTERVAL);
+                if rx.try_iter().count() == 0 {
+                    break;
+                }
+            }
+        }
+    });
+    tx
+}
+
+fn run_command(command: &str) -> anyhow::Result<FocusReport> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        // Its own process group, so that a timeout kills what the shell started as well
+        .process_group(0)
+        .spawn()?;
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let timed_out = |child: &mut Child| {
+        let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+        let _ = child.wait();
+        anyhow::anyhow!("{:?} didn't finish in {:?}", command, COMMAND_TIMEOUT)
+    };
+
+    // Read stdout on another thread, which can stay blocked only until the process group is killed
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = tx.send(stdout.read_to_string(&mut output).map(|_| output));
+    });
+    let output = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output?,
+        Err(_) => return Err(timed_out(&mut child)),
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            return Err(timed_out(&mut child));
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    if !status.success() {
+        anyhow::bail!("{:?} failed with {}", command, status);
+    }
+    FocusReport::parse(&output)
+}
+
+// Read JSON lines from a named pipe or a Unix socket that a long-running helper writes to.
+// The last line is the focused window.
+pub struct PipeClient {
+    path: PathBuf,
+    focus: Arc<Mutex<FocusReport>>,
+    subscribed: bool,
+}
+
+impl PipeClient {
+    pub fn new(path: PathBuf) -> PipeClient {
+        PipeClient {
+            path,
+            focus: Arc::new(Mutex::new(FocusReport::default())),
+            subscribed: false,
+        }
+    }
+
+    fn focus(&self) -> FocusReport {
+        self.focus.lock().unwrap().clone()
+    }
+}
+
+impl Client for PipeClient {
+    fn supported(&mut self) -> bool {
+        if !self.path.exists() {
+            println!("PipeClient: {} doesn't exist", self.path.display());
+            return false;
+        }
+        if !self.subscribed {
+            subscribe(self.path.clone(), self.focus.clone());
+            self.subscribed = true;
+        }
+        true
+    }
+
+    fn current_window(&mut self) -> Option<String> {
+        self.focus().window
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        self.focus().application
+    }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        self.focus().properties()
+    }
+}
+
+// Reopen the pipe or the socket whenever the writer goes away, e.g. when the helper restarts
+fn subscribe(path: PathBuf, focus: Arc<Mutex<FocusReport>>) {
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            match read_focus(&path, &focus) {
+                // A writer has closed the named pipe. Keep the focus until the next one writes.
+                Ok(()) if is_fifo(&path) => continue,
+                Ok(()) => {}
+                Err(e) if !reported => {
+                    println!("PipeClient failed to read {}, retrying quietly: {e}", path.display());
+                    reported = true;
+                }
+                Err(_) => {}
+            }
+            *focus.lock().unwrap() = FocusReport::default();
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+}
+
+fn is_fifo(path: &Path) -> bool {
+    path.metadata().is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+fn read_focus(path: &Path, focus: &Mutex<FocusReport>) -> anyhow::Result<()> {
+    let reader: Box<dyn Read> = if path.metadata()?.file_type().is_socket() {
+        Box::new(UnixStream::connect(path)?)
+    } else {
+        // Opening a named pipe waits for a writer
+        Box::new(File::open(path)?)
+    };
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<FocusReport>(&line) {
+            Ok(report) => *focus.lock().unwrap() = report,
+            Err(e) => log::error!("PipeClient: unexpected line {line:?}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_focus_report() {
+        let report = FocusReport::parse("{\"app\": \"foot\", \"title\": \"~\", \"pid\": 1234}\n").unwrap();
+        assert_eq!(Some("foot"), report.application.as_deref());
+        assert_eq!(Some("~"), report.window.as_deref());
+        assert_eq!(Some(1234), report.pid);
+
+        let report = FocusReport::parse("firefox\nMozilla Firefox\n").unwrap();
+        assert_eq!(Some("firefox"), report.application.as_deref());
+        assert_eq!(Some("Mozilla Firefox"), report.window.as_deref());
+
+        assert_eq!(FocusReport::default(), FocusReport::parse("").unwrap());
+        assert!(FocusReport::parse("{\"application\": 1}").is_err());
+    }
+
+    #[test]
+    fn test_command_client() {
+        // The command runs on another thread, so key events don't wait for it
+        let mut client = CommandClient::new(String::from("sleep 0.3; printf 'foot\\n~\\n'"));
+        assert!(client.supported());
+        let start = Instant::now();
+        assert_eq!(Some(String::from("foot")), client.current_application());
+        assert_eq!(Some(String::from("~")), client.current_window());
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        // A hanging command is killed with what it started
+        let start = Instant::now();
+        assert!(run_command("sleep 10 & sleep 10").is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
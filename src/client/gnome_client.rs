@@ -1,70 +1,315 @@
 use crate::client::Client;
+use anyhow::{anyhow, bail};
 use serde::{Deserialize, Serialize};
-use zbus::Connection;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{fdo, Connection, Message};
 
 pub struct GnomeClient {
     connection: Option<Connection>,
+    // The first method that worked in supported()
+    method: Option<Method>,
+    // Kept up to date by the AT-SPI thread
+    atspi_focus: Arc<Mutex<Focus>>,
+    // Whether the failure of the method has been reported since it last worked
+    reported: bool,
+}
+
+// Ways to get the focused window, in the order of preference
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    // The xremap GNOME Shell extension, which exposes /com/k0kubun/Xremap
+    Extension,
+    // org.gnome.Shell.Introspect.GetWindows, which needs unsafe-mode on older versions
+    Introspect,
+    // window:activate events of the accessibility bus
+    AtSpi,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Extension => write!(f, "the xremap GNOME Shell extension"),
+            Method::Introspect => write!(f, "org.gnome.Shell.Introspect"),
+            Method::AtSpi => write!(f, "AT-SPI"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Focus {
+    application: Option<String>,
+    window: Option<String>,
 }
 
 impl GnomeClient {
     pub fn new() -> GnomeClient {
-        GnomeClient { connection: None }
+        GnomeClient {
+            connection: None,
+            method: None,
+            atspi_focus: Arc::new(Mutex::new(Focus::default())),
+            reported: false,
+        }
     }
 
     fn connect(&mut self) {
+        if self.connection.is_some() {
+            return;
+        }
         match Connection::new_session() {
             Ok(connection) => self.connection = Some(connection),
             Err(e) => println!("GnomeClient#connect() failed: {}", e),
         }
     }
+
+    fn try_method(&mut self, method: Method) -> anyhow::Result<()> {
+        let connection = self.connection.as_ref().ok_or_else(|| anyhow!("no session bus"))?;
+        match method {
+            Method::Extension => extension_focus(connection).map(|_| ()),
+            Method::Introspect => introspect_focus(connection).map(|_| ()),
+            Method::AtSpi => watch_atspi(connection, self.atspi_focus.clone()),
+        }
+    }
+
+    fn focus(&mut self) -> Focus {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return Focus::default(),
+        };
+        let focus = match self.method {
+            Some(Method::Extension) => extension_focus(connection),
+            Some(Method::Introspect) => introspect_focus(connection),
+            Some(Method::AtSpi) => return self.atspi_focus.lock().unwrap().clone(),
+            None => return Focus::default(),
+        };
+        match focus {
+            Ok(focus) => {
+                self.reported = false;
+                focus
+            }
+            Err(e) => {
+                // This is asked on every key press. Don't flood the log while GNOME Shell is away.
+                if !self.reported {
+                    println!("GnomeClient failed to get the focused window, retrying quietly: {e}");
+                    self.reported = true;
+                }
+                Focus::default()
+            }
+        }
+    }
 }
 
 impl Client for GnomeClient {
     fn supported(&mut self) -> bool {
         self.connect();
-        self.current_application().is_some()
+        // Explain which method is used, and why the preferred ones didn't work
+        let mut failures = vec![];
+        for method in [Method::Extension, Method::Introspect, Method::AtSpi] {
+            match self.try_method(method) {
+                Ok(()) => {
+                    println!("GnomeClient: using {method}");
+                    for failure in &failures {
+                        println!("GnomeClient:   {failure}");
+                    }
+                    self.method = Some(method);
+                    return true;
+                }
+                Err(e) => failures.push(format!("{method} is unavailable: {e}")),
+            }
+        }
+        println!("GnomeClient: no way to get the focused window is available");
+        for failure in &failures {
+            println!("GnomeClient:   {failure}");
+        }
+        false
     }
+
     fn current_window(&mut self) -> Option<String> {
-        // TODO:  not implemented
-        None
+        self.focus().window
     }
 
     fn current_application(&mut self) -> Option<String> {
-        self.connect();
-        let connection = match &mut self.connection {
-            Some(connection) => connection,
-            None => return None,
-        };
+        self.focus().application
+    }
+}
+
+fn extension_focus(connection: &Connection) -> anyhow::Result<Focus> {
+    // Attempt the latest protocol
+    if let Ok(message) = connection.call_method(
+        Some("org.gnome.Shell"),
+        "/com/k0kubun/Xremap",
+        Some("com.k0kubun.Xremap"),
+        "ActiveWindow",
+        &(),
+    ) {
+        let window = serde_json::from_str::<ActiveWindow>(&message.body::<String>()?)?;
+        return Ok(Focus {
+            application: Some(window.wm_class),
+            window: Some(window.title),
+        });
+    }
+    // Fallback to the legacy protocol
+    let message = connection.call_method(
+        Some("org.gnome.Shell"),
+        "/com/k0kubun/Xremap",
+        Some("com.k0kubun.Xremap"),
+        "WMClass",
+        &(),
+    )?;
+    Ok(Focus {
+        application: Some(message.body::<String>()?),
+        window: None,
+    })
+}
 
-        // Attempt the latest protocol
-        if let Ok(message) = connection.call_method(
+fn introspect_focus(connection: &Connection) -> anyhow::Result<Focus> {
+    let windows: HashMap<u64, HashMap<String, OwnedValue>> = connection
+        .call_method(
             Some("org.gnome.Shell"),
-            "/com/k0kubun/Xremap",
-            Some("com.k0kubun.Xremap"),
-            "ActiveWindow",
+            "/org/gnome/Shell/Introspect",
+            Some("org.gnome.Shell.Introspect"),
+            "GetWindows",
             &(),
-        ) {
-            if let Ok(json) = message.body::<String>() {
-                if let Ok(window) = serde_json::from_str::<ActiveWindow>(&json) {
-                    return Some(window.wm_class);
+        )?
+        .body()?;
+    Ok(focus_from_windows(&windows))
+}
+
+// The focused one of GetWindows. Its application is wm-class like the extension, or app-id without it.
+fn focus_from_windows(windows: &HashMap<u64, HashMap<String, OwnedValue>>) -> Focus {
+    let string = |window: &HashMap<String, OwnedValue>, key: &str| {
+        window
+            .get(key)
+            .and_then(|value| <&str>::try_from(value).ok())
+            .filter(|value| !value.is_empty())
+            .map(String::from)
+    };
+    windows
+        .values()
+        .find(|window| window.get("has-focus").and_then(|value| bool::try_from(value).ok()) == Some(true))
+        .map(|window| Focus {
+            application: string(window, "wm-class").or_else(|| string(window, "app-id")),
+            window: string(window, "title"),
+        })
+        .unwrap_or_default()
+}
+
+// Follow window:activate events of the accessibility bus on a thread
+fn watch_atspi(session: &Connection, focus: Arc<Mutex<Focus>>) -> anyhow::Result<()> {
+    let (mut events, mut queries) = connect_atspi(session)?;
+    let session = session.clone();
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            if let Err(e) = follow_activations(&events, &queries, &focus) {
+                if !reported {
+                    println!("GnomeClient lost the accessibility bus, reconnecting quietly: {e}");
+                    reported = true;
                 }
             }
-        // Fallback to the legacy protocol
-        } else if let Ok(message) = connection.call_method(
-            Some("org.gnome.Shell"),
-            "/com/k0kubun/Xremap",
-            Some("com.k0kubun.Xremap"),
-            "WMClass",
-            &(),
-        ) {
-            if let Ok(wm_class) = message.body::<String>() {
-                return Some(wm_class);
-            }
+            *focus.lock().unwrap() = Focus::default();
+            // The bus goes away when GNOME Shell or at-spi2-registryd restarts
+            (events, queries) = loop {
+                thread::sleep(Duration::from_secs(1));
+                if let Ok(connections) = connect_atspi(&session) {
+                    break connections;
+                }
+            };
+        }
+    });
+    Ok(())
+}
+
+fn follow_activations(events: &Connection, queries: &Connection, focus: &Mutex<Focus>) -> zbus::Result<()> {
+    loop {
+        let message = events.receive_message()?;
+        if let Some(activated) = activated_window(queries, &message) {
+            *focus.lock().unwrap() = activated;
         }
-        None
     }
 }
 
+// A connection to receive events of the accessibility bus and another to query applications
+fn connect_atspi(session: &Connection) -> anyhow::Result<(Connection, Connection)> {
+    let enabled: OwnedValue = session
+        .call_method(
+            Some("org.a11y.Bus"),
+            "/org/a11y/bus",
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &("org.a11y.Status", "IsEnabled"),
+        )?
+        .body()?;
+    if bool::try_from(enabled) != Ok(true) {
+        bail!("accessibility is disabled. Enable it with `gsettings set org.gnome.desktop.interface toolkit-accessibility true`");
+    }
+    let address: String = session
+        .call_method(Some("org.a11y.Bus"), "/org/a11y/bus", Some("org.a11y.Bus"), "GetAddress", &())?
+        .body()?;
+    let events = Connection::new_for_address(&address, true)?;
+    let queries = Connection::new_for_address(&address, true)?;
+    fdo::DBusProxy::new(&events)?
+        .add_match("type='signal',interface='org.a11y.atspi.Event.Window',member='Activate'")?;
+    // Ask applications to emit the event. The registry of at-spi2-core 2.46 or later takes more arguments.
+    queries
+        .call_method(
+            Some("org.a11y.atspi.Registry"),
+            "/org/a11y/atspi/registry",
+            Some("org.a11y.atspi.Registry"),
+            "RegisterEvent",
+            &("window:activate", Vec::<String>::new(), ""),
+        )
+        .or_else(|_| {
+            queries.call_method(
+                Some("org.a11y.atspi.Registry"),
+                "/org/a11y/atspi/registry",
+                Some("org.a11y.atspi.Registry"),
+                "RegisterEvent",
+                &("window:activate"),
+            )
+        })?;
+    Ok((events, queries))
+}
+
+// The application and title of the window of an Activate event
+fn activated_window(queries: &Connection, message: &Message) -> Option<Focus> {
+    let header = message.header().ok()?;
+    if header.interface().ok()?? != "org.a11y.atspi.Event.Window" || header.member().ok()?? != "Activate" {
+        return None;
+    }
+    let sender = header.sender().ok()??.to_string();
+    let path = header.path().ok()??.to_string();
+    let (app_sender, app_path): (String, OwnedObjectPath) = queries
+        .call_method(Some(sender.as_str()), &path, Some("org.a11y.atspi.Accessible"), "GetApplication", &())
+        .ok()?
+        .body()
+        .ok()?;
+    Some(Focus {
+        application: accessible_name(queries, &app_sender, app_path.as_str()),
+        window: accessible_name(queries, &sender, &path),
+    })
+}
+
+fn accessible_name(queries: &Connection, destination: &str, path: &str) -> Option<String> {
+    let name: OwnedValue = queries
+        .call_method(
+            Some(destination),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &("org.a11y.atspi.Accessible", "Name"),
+        )
+        .ok()?
+        .body()
+        .ok()?;
+    String::try_from(name).ok().filter(|name| !name.is_empty())
+}
+
 #[derive(Serialize, Deserialize)]
 struct ActiveWindow {
     #[serde(default)]
@@ -72,3 +317,37 @@ struct ActiveWindow {
     #[serde(default)]
     title: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::Value;
+
+    #[test]
+    fn test_focus_from_windows() {
+        let window = |focused: bool, wm_class: &str, app_id: &str, title: &str| -> HashMap<String, OwnedValue> {
+            [
+                ("has-focus", Value::from(focused)),
+                ("wm-class", Value::from(wm_class)),
+                ("app-id", Value::from(app_id)),
+                ("title", Value::from(title)),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), OwnedValue::from(value)))
+            .collect()
+        };
+        let mut windows = HashMap::new();
+        windows.insert(1, window(false, "firefox", "firefox.desktop", "Mozilla Firefox"));
+        windows.insert(2, window(true, "", "org.gnome.Console.desktop", "~"));
+        assert_eq!(
+            Focus {
+                application: Some(String::from("org.gnome.Console.desktop")),
+                window: Some(String::from("~")),
+            },
+            focus_from_windows(&windows)
+        );
+
+        windows.remove(&2);
+        assert_eq!(Focus::default(), focus_from_windows(&windows));
+    }
+}
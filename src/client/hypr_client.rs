@@ -1,5 +1,8 @@
-use crate::client::Client;
-use hyprland::{data::Client as HyprClient, prelude::*};
+use crate::client::{Client, WindowProperties};
+use hyprland::{
+    data::{Client as HyprClient, Monitors},
+    prelude::*,
+};
 pub struct HyprlandClient;
 
 impl HyprlandClient {
@@ -25,4 +28,25 @@ impl Client for HyprlandClient {
         }
         None
     }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        let win = match HyprClient::get_active() {
+            Ok(Some(win)) => win,
+            _ => return WindowProperties::default(),
+        };
+        let output = Monitors::get()
+            .ok()
+            .and_then(|monitors| monitors.into_iter().find(|monitor| monitor.id == win.monitor))
+            .map(|monitor| monitor.name);
+        WindowProperties {
+            pid: u32::try_from(win.pid).ok(),
+            executable: None,
+            workspace: Some(win.workspace.name),
+            output,
+            floating: Some(win.floating),
+            fullscreen: Some(win.fullscreen),
+            xwayland: Some(win.xwayland),
+            ..Default::default()
+        }
+    }
 }
@@ -1,28 +1,55 @@
 use log::{debug, warn};
-use std::env::temp_dir;
+use nix::unistd::getuid;
+use std::env::{self, temp_dir};
+use std::fs::{create_dir_all, File};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
-use std::sync::{Arc, Mutex};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 use crate::client::Client;
-use zbus::{dbus_interface, fdo, Connection};
+use zbus::{fdo, Connection};
 
 const KWIN_SCRIPT: &str = include_str!("kwin-script.js");
 const KWIN_SCRIPT_PLUGIN_NAME: &str = "xremap";
 
-pub struct KdeClient {
-    active_window: Arc<Mutex<ActiveWindow>>,
+// The KWin script pushes the active window to the D-Bus service of push_service, which WMClient prefers.
+// So this client only loads the script.
+pub struct KdeClient;
+
+// The script is written to a file only for KWin to load it, and removed once it's running.
+// The file name is per process so that xremap of another user or session never races for it.
+struct KwinScriptFile(PathBuf);
+
+impl KwinScriptFile {
+    fn write() -> Result<Self, ConnectionError> {
+        let dir = script_dir();
+        create_dir_all(&dir).map_err(|_| ConnectionError::WriteScript)?;
+        let file = Self(dir.join(format!("kwin-script-{}.js", process::id())));
+        File::create(&file.0)
+            .and_then(|mut f| f.write_all(KWIN_SCRIPT.as_bytes()))
+            .map_err(|_| ConnectionError::WriteScript)?;
+        Ok(file)
+    }
 }
 
-struct KwinScriptTempFile(PathBuf);
-
-impl KwinScriptTempFile {
-    fn new() -> Self {
-        Self(temp_dir().join("xremap-kwin-script.js"))
+// The runtime directory of the user, which KWin can read even when xremap runs with sudo
+fn script_dir() -> PathBuf {
+    if let Ok(dir) = env::var("XDG_RUNTIME_DIR") {
+        return Path::new(&dir).join("xremap");
+    }
+    let uid = env::var("SUDO_UID").unwrap_or_else(|_| getuid().to_string());
+    let dir = PathBuf::from(format!("/run/user/{uid}"));
+    if dir.is_dir() {
+        dir.join("xremap")
+    } else {
+        temp_dir().join(format!("xremap-{uid}"))
     }
 }
 
-impl Drop for KwinScriptTempFile {
+impl Drop for KwinScriptFile {
     fn drop(&mut self) {
         let _ = std::fs::remove_file(&self.0);
     }
@@ -42,8 +69,8 @@ impl KWinScripting for Connection {
             "/Scripting",
             Some("org.kde.kwin.Scripting"),
             "loadScript",
-            // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
-            &(path.to_str().ok_or(ConnectionError::TempPathNotValidUtf8)?, KWIN_SCRIPT_PLUGIN_NAME),
+            // since OsStr does not implement zvariant::Type, the path must be valid utf-8
+            &(path.to_str().ok_or(ConnectionError::ScriptPathNotValidUtf8)?, KWIN_SCRIPT_PLUGIN_NAME),
         )
         .map_err(|_| ConnectionError::LoadScriptCall)?
         .body::<i32>()
@@ -56,7 +83,6 @@ impl KWinScripting for Connection {
             "/Scripting",
             Some("org.kde.kwin.Scripting"),
             "unloadScript",
-            // since OsStr does not implement zvariant::Type, the temp-path must be valid utf-8
             &KWIN_SCRIPT_PLUGIN_NAME,
         )
         .map_err(|_| ConnectionError::UnloadScriptCall)?
@@ -98,11 +124,14 @@ impl KWinScripting for Connection {
 
 fn load_kwin_script() -> Result<(), ConnectionError> {
     let dbus = Connection::new_session().map_err(|_| ConnectionError::ClientSession)?;
+    load_kwin_script_with(&dbus)
+}
+
+fn load_kwin_script_with(dbus: &Connection) -> Result<(), ConnectionError> {
     if !dbus.is_script_loaded()? {
         let init_script = || {
-            let temp_file_path = KwinScriptTempFile::new();
-            std::fs::write(&temp_file_path.0, KWIN_SCRIPT).map_err(|_| ConnectionError::WriteScriptToTempFile)?;
-            let script_obj_id = dbus.load_script(&temp_file_path.0)?;
+            let script_file = KwinScriptFile::write()?;
+            let script_obj_id = dbus.load_script(&script_file.0)?;
             dbus.start_script(script_obj_id)?;
             Ok(())
         };
@@ -119,75 +148,66 @@ fn load_kwin_script() -> Result<(), ConnectionError> {
     Ok(())
 }
 
-impl KdeClient {
-    pub fn new() -> KdeClient {
-        let active_window = Arc::new(Mutex::new(ActiveWindow {
-            title: String::new(),
-            res_name: String::new(),
-            res_class: String::new(),
-        }));
-        KdeClient { active_window }
-    }
+// A restarted KWin doesn't have the script anymore. Load it again when org.kde.KWin gets a new owner.
+fn watch_kwin() {
+    thread::spawn(|| {
+        if let Err(err) = watch_kwin_owner() {
+            println!("Stopped watching KWin restarts: {err:?}");
+        }
+    });
+}
 
-    fn connect(&mut self) -> Result<(), ConnectionError> {
-        load_kwin_script()?;
-
-        let active_window = Arc::clone(&self.active_window);
-        let (tx, rx) = channel();
-        std::thread::spawn(move || {
-            let connect = move || {
-                let connection = Connection::new_session().map_err(|_| ConnectionError::ServerSession)?;
-                fdo::DBusProxy::new(&connection)
-                    .map_err(|_| ConnectionError::CreateDBusProxy)?
-                    .request_name("com.k0kubun.Xremap", fdo::RequestNameFlags::ReplaceExisting.into())
-                    .map_err(|_| ConnectionError::RequestName)?;
-                let mut object_server = zbus::ObjectServer::new(&connection);
-                let awi = ActiveWindowInterface { active_window };
-                object_server
-                    .at(&"/com/k0kubun/Xremap".try_into().unwrap(), awi)
-                    .map_err(|_| ConnectionError::ServeObjServer)?;
-                Ok(object_server)
-            };
-            let object_server: Result<zbus::ObjectServer, ConnectionError> = connect();
-            match object_server {
-                Ok(mut object_server) => {
-                    let _ = tx.send(Ok(()));
-                    loop {
-                        if let Err(err) = object_server.try_handle_next() {
-                            eprintln!("{}", err);
-                        }
-                    }
+fn watch_kwin_owner() -> Result<(), ConnectionError> {
+    let dbus = Connection::new_session().map_err(|_| ConnectionError::ClientSession)?;
+    let proxy = fdo::DBusProxy::new(&dbus).map_err(|_| ConnectionError::CreateDBusProxy)?;
+    let restarted = Arc::new(AtomicBool::new(false));
+    {
+        let restarted = Arc::clone(&restarted);
+        proxy
+            .connect_name_owner_changed(move |name, _, new_owner| {
+                // An empty new owner means that KWin has quit
+                if name == "org.kde.KWin" && !new_owner.is_empty() {
+                    restarted.store(true, Ordering::Relaxed);
                 }
-                Err(err) => tx.send(Err(err)),
+                Ok(())
+            })
+            .map_err(|_| ConnectionError::WatchNameOwner)?;
+    }
+    loop {
+        proxy.next_signal().map_err(|_| ConnectionError::WatchNameOwner)?;
+        if restarted.swap(false, Ordering::Relaxed) {
+            match load_kwin_script_with(&dbus) {
+                Ok(()) => println!("Reloaded kwin-script into KWin"),
+                Err(err) => println!("Failed to reload kwin-script into KWin: {err:?}"),
             }
-        });
-        rx.recv().unwrap()
+        }
     }
 }
 
 impl Client for KdeClient {
     fn supported(&mut self) -> bool {
-        let conn_res = self.connect();
-        if let Err(err) = &conn_res {
+        if let Err(err) = load_kwin_script() {
             warn!("Could not connect to kwin-script. Error: {err:?}");
+            return false;
         }
-        conn_res.is_ok()
+        watch_kwin();
+        true
     }
+
+    // Asked only until the script has pushed the active window
     fn current_window(&mut self) -> Option<String> {
-        let aw = self.active_window.lock().ok()?;
-        Some(aw.title.clone())
+        None
     }
 
     fn current_application(&mut self) -> Option<String> {
-        let aw = self.active_window.lock().ok()?;
-        Some(aw.res_class.clone())
+        None
     }
 }
 
 #[derive(Debug)]
 enum ConnectionError {
-    TempPathNotValidUtf8,
-    WriteScriptToTempFile,
+    ScriptPathNotValidUtf8,
+    WriteScript,
     ClientSession,
 
     LoadScriptCall,
@@ -201,30 +221,7 @@ enum ConnectionError {
     IsScriptLoadedCall,
     InvalidIsScriptLoadedResult,
 
-    ServerSession,
     CreateDBusProxy,
-    RequestName,
-    ServeObjServer,
-}
 
-struct ActiveWindow {
-    res_class: String,
-    res_name: String,
-    title: String,
-}
-
-struct ActiveWindowInterface {
-    active_window: Arc<Mutex<ActiveWindow>>,
-}
-
-#[dbus_interface(name = "com.k0kubun.Xremap")]
-impl ActiveWindowInterface {
-    fn notify_active_window(&mut self, caption: String, res_class: String, res_name: String) {
-        // I want to always print this, since it is the only way to know what the resource class of applications is.
-        println!("active window: caption: '{caption}', class: '{res_class}', name: '{res_name}'");
-        let mut aw = self.active_window.lock().unwrap();
-        aw.title = caption;
-        aw.res_class = res_class;
-        aw.res_name = res_name;
-    }
+    WatchNameOwner,
 }
@@ -1,15 +1,37 @@
 function notifyActiveWindow(client) {
     callDBus(
-        "com.k0kubun.Xremap",
-        "/com/k0kubun/Xremap",
-        "com.k0kubun.Xremap",
-        "NotifyActiveWindow",
+        "com.k0kubun.Xremap.Focus",
+        "/com/k0kubun/Xremap/Focus",
+        "com.k0kubun.Xremap.Focus",
+        "NotifyKWinWindow",
         "caption" in client ? client.caption : "",
         "resourceClass" in client ? client.resourceClass : "",
-        "resourceName" in client ? client.resourceName : ""
+        "resourceName" in client ? client.resourceName : "",
+        "pid" in client ? client.pid : 0,
+        desktopName(client),
+        outputName(client),
+        "fullScreen" in client ? client.fullScreen : false
     );
 }
 
+function desktopName(client) {
+    if (client.desktops && client.desktops.length > 0) {
+        // kde 6
+        return client.desktops[0].name;
+    }
+    // kde 5
+    return "desktop" in client ? String(client.desktop) : "";
+}
+
+function outputName(client) {
+    if (client.output) {
+        // kde 6
+        return client.output.name;
+    }
+    // kde 5
+    return "screen" in client ? String(client.screen) : "";
+}
+
 if (workspace.windowList) {
     // kde 6
     workspace.windowActivated.connect(notifyActiveWindow);
@@ -17,3 +39,9 @@ if (workspace.windowList) {
     // kde 5
     workspace.clientActivated.connect(notifyActiveWindow);
 }
+
+// Tell the window that's active already, e.g. after KWin has restarted
+const activeWindow = workspace.activeWindow || workspace.activeClient;
+if (activeWindow) {
+    notifyActiveWindow(activeWindow);
+}
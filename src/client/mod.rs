@@ -1,7 +1,30 @@
+use push_service::{fresh_push, PushedFocus, Pushes};
+use std::fs::read_link;
+
 pub trait Client {
     fn supported(&mut self) -> bool;
     fn current_application(&mut self) -> Option<String>;
     fn current_window(&mut self) -> Option<String>;
+    // Properties of the focused window for `window_properties`. Only what the client knows is filled.
+    fn window_properties(&mut self) -> WindowProperties {
+        WindowProperties::default()
+    }
+}
+
+// Properties of the focused window other than the application and title. None if unknown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowProperties {
+    pub pid: Option<u32>,
+    // The path of the executable. WMClient fills it from pid.
+    pub executable: Option<String>,
+    pub workspace: Option<String>,
+    pub output: Option<String>,
+    pub floating: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub xwayland: Option<bool>,
+    // resourceName and resourceClass of a KDE window. The class is also its application.
+    pub resource_name: Option<String>,
+    pub resource_class: Option<String>,
 }
 
 pub struct WMClient {
@@ -10,6 +33,8 @@ pub struct WMClient {
     supported: Option<bool>,
     last_application: String,
     last_window: String,
+    // The focus pushed through the D-Bus service, which is preferred over `client` while it's fresh
+    pushes: Option<Pushes>,
 }
 
 impl WMClient {
@@ -20,19 +45,33 @@ impl WMClient {
             supported: None,
             last_application: String::new(),
             last_window: String::new(),
+            pushes: None,
         }
     }
-    pub fn current_window(&mut self) -> Option<String> {
+
+    pub fn accept_pushes(&mut self, pushes: Pushes) {
+        self.pushes = Some(pushes);
+    }
+
+    fn pushed(&self) -> Option<PushedFocus> {
+        fresh_push(self.pushes.as_ref()?)
+    }
+
+    fn supported(&mut self) -> bool {
         if self.supported.is_none() {
             let supported = self.client.supported();
             self.supported = Some(supported);
             println!("application-client: {} (supported: {})", self.name, supported);
         }
-        if !self.supported.unwrap() {
-            return None;
-        }
+        self.supported.unwrap()
+    }
 
-        let result = self.client.current_window();
+    pub fn current_window(&mut self) -> Option<String> {
+        let result = match self.pushed() {
+            Some(pushed) => pushed.window,
+            None if self.supported() => self.client.current_window(),
+            None => return None,
+        };
         if let Some(window) = &result {
             if &self.last_window != window {
                 self.last_window = window.clone();
@@ -43,16 +82,11 @@ impl WMClient {
     }
 
     pub fn current_application(&mut self) -> Option<String> {
-        if self.supported.is_none() {
-            let supported = self.client.supported();
-            self.supported = Some(supported);
-            println!("application-client: {} (supported: {})", self.name, supported);
-        }
-        if !self.supported.unwrap() {
-            return None;
-        }
-
-        let result = self.client.current_application();
+        let result = match self.pushed() {
+            Some(pushed) => pushed.application,
+            None if self.supported() => self.client.current_application(),
+            None => return None,
+        };
         if let Some(application) = &result {
             if &self.last_application != application {
                 self.last_application = application.clone();
@@ -61,8 +95,27 @@ impl WMClient {
         }
         result
     }
+
+    pub fn window_properties(&mut self) -> WindowProperties {
+        let mut properties = match self.pushed() {
+            Some(pushed) => pushed.properties,
+            None if self.supported() => self.client.window_properties(),
+            None => return WindowProperties::default(),
+        };
+        if properties.executable.is_none() {
+            properties.executable = properties
+                .pid
+                .and_then(|pid| read_link(format!("/proc/{pid}/exe")).ok())
+                .and_then(|path| path.into_os_string().into_string().ok());
+        }
+        properties
+    }
 }
 
+pub mod external_client;
+pub mod push_service;
+pub mod static_client;
+
 #[cfg(feature = "gnome")]
 mod gnome_client;
 #[cfg(feature = "gnome")]
@@ -74,7 +127,7 @@ pub fn build_client() -> WMClient {
 mod kde_client;
 #[cfg(feature = "kde")]
 pub fn build_client() -> WMClient {
-    WMClient::new("KDE", Box::new(kde_client::KdeClient::new()))
+    WMClient::new("KDE", Box::new(kde_client::KdeClient))
 }
 
 #[cfg(feature = "sway")]
@@ -105,12 +158,36 @@ pub fn build_client() -> WMClient {
     WMClient::new("wlroots", Box::new(wlroots_client::WlRootsClient::new()))
 }
 
+#[cfg(feature = "niri")]
+mod niri_client;
+#[cfg(feature = "niri")]
+pub fn build_client() -> WMClient {
+    WMClient::new("niri", Box::new(niri_client::NiriClient::new()))
+}
+
+#[cfg(feature = "river")]
+mod river_client;
+#[cfg(feature = "river")]
+pub fn build_client() -> WMClient {
+    WMClient::new("river", Box::new(river_client::RiverClient::new()))
+}
+
+// Shared by the wlroots client and the niri/river clients
+#[cfg(any(feature = "wlroots", feature = "niri"))]
+mod niri_ipc;
+#[cfg(any(feature = "wlroots", feature = "river"))]
+mod river_status;
+#[cfg(any(feature = "wlroots", feature = "river"))]
+mod wayland_toplevels;
+
 #[cfg(not(any(
     feature = "gnome",
     feature = "sway",
     feature = "x11",
     feature = "hypr",
-    feature = "kde"
+    feature = "kde",
+    feature = "niri",
+    feature = "river"
 )))]
 mod null_client;
 #[cfg(not(any(
@@ -119,7 +196,9 @@ mod null_client;
     feature = "x11",
     feature = "hypr",
     feature = "kde",
-    feature = "wlroots"
+    feature = "wlroots",
+    feature = "niri",
+    feature = "river"
 )))]
 pub fn build_client() -> WMClient {
     WMClient::new("none", Box::new(null_client::NullClient))
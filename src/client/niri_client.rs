@@ -0,0 +1,45 @@
+use crate::client::niri_ipc::{self, NiriWindow};
+use crate::client::{Client, WindowProperties};
+
+pub struct NiriClient;
+
+impl NiriClient {
+    pub fn new() -> NiriClient {
+        NiriClient {}
+    }
+}
+
+impl Client for NiriClient {
+    fn supported(&mut self) -> bool {
+        if !niri_ipc::is_available() {
+            println!("NiriClient: $NIRI_SOCKET is not set");
+            return false;
+        }
+        match niri_ipc::focused_window() {
+            Ok(_) => true,
+            Err(e) => {
+                println!("NiriClient#supported() failed: {e}");
+                false
+            }
+        }
+    }
+
+    fn current_window(&mut self) -> Option<String> {
+        niri_ipc::focused_window_or_log()?.title
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        niri_ipc::focused_window_or_log()?.app_id
+    }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        match niri_ipc::focused_window_or_log() {
+            Some(NiriWindow { pid, is_floating, .. }) => WindowProperties {
+                pid,
+                floating: is_floating,
+                ..Default::default()
+            },
+            None => WindowProperties::default(),
+        }
+    }
+}
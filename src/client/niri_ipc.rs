@@ -0,0 +1,76 @@
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+// niri's IPC: A JSON request and a JSON reply per line on $NIRI_SOCKET
+#[derive(Debug, Deserialize)]
+pub struct NiriWindow {
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub pid: Option<u32>,
+    pub is_floating: Option<bool>,
+}
+
+#[derive(Deserialize)]
+enum Reply {
+    Ok(Response),
+    Err(String),
+}
+
+#[derive(Deserialize)]
+enum Response {
+    FocusedWindow(Option<NiriWindow>),
+}
+
+pub fn is_available() -> bool {
+    env::var_os("NIRI_SOCKET").is_some()
+}
+
+pub fn focused_window() -> anyhow::Result<Option<NiriWindow>> {
+    let path = env::var("NIRI_SOCKET").context("$NIRI_SOCKET is not set")?;
+    let mut stream = UnixStream::connect(&path).with_context(|| format!("Failed to connect to {path}"))?;
+    stream.write_all(b"\"FocusedWindow\"\n")?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    parse_focused_window(&reply)
+}
+
+// focused_window() for clients, which report errors and go on
+pub fn focused_window_or_log() -> Option<NiriWindow> {
+    match focused_window() {
+        Ok(window) => window,
+        Err(err) => {
+            log::error!("{err}");
+            None
+        }
+    }
+}
+
+fn parse_focused_window(reply: &str) -> anyhow::Result<Option<NiriWindow>> {
+    match serde_json::from_str(reply).context("Unexpected reply from niri")? {
+        Reply::Ok(Response::FocusedWindow(window)) => Ok(window),
+        Reply::Err(error) => Err(anyhow!("niri replied with an error: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_focused_window() {
+        let reply = r#"{"Ok":{"FocusedWindow":{"id":3,"title":"~","app_id":"foot","pid":1234,"workspace_id":1,"is_focused":true}}}"#;
+        let window = parse_focused_window(reply).unwrap().unwrap();
+        assert_eq!(Some("foot"), window.app_id.as_deref());
+        assert_eq!(Some("~"), window.title.as_deref());
+        assert_eq!(Some(1234), window.pid);
+        assert_eq!(None, window.is_floating);
+
+        assert!(parse_focused_window(r#"{"Ok":{"FocusedWindow":null}}"#)
+            .unwrap()
+            .is_none());
+        assert!(parse_focused_window(r#"{"Err":"unknown request"}"#).is_err());
+    }
+}
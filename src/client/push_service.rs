@@ -0,0 +1,256 @@
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zbus::{dbus_interface, fdo, Connection, Message, MessageHeader, ObjectServer};
+
+use crate::client::WindowProperties;
+
+// A D-Bus service on the session bus that any extension, script or compositor plugin can push the focus to:
+//
+//   busctl --user call com.k0kubun.Xremap.Focus /com/k0kubun/Xremap/Focus com.k0kubun.Xremap.Focus \
+//     NotifyFocusFor ssuu firefox "Mozilla Firefox" 1234 0
+//
+// An empty string or 0 means unknown. NotifyFocus(application, window, pid) is for plugins and helpers that stay
+// connected, and the focus is forgotten when its sender leaves the bus. NotifyFocusFor takes seconds to keep the
+// focus for one-shot commands like the above, where 0 means until the next push. ClearFocus forgets it.
+// The KWin script of the KDE client pushes with NotifyKWinWindow, which takes more properties.
+const SERVICE_NAME: &str = "com.k0kubun.Xremap.Focus";
+const OBJECT_PATH: &str = "/com/k0kubun/Xremap/Focus";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PushedFocus {
+    pub application: Option<String>,
+    pub window: Option<String>,
+    pub properties: WindowProperties,
+    // The unique name of the connection that pushed it with NotifyFocus
+    sender: Option<String>,
+    // When the focus pushed with NotifyFocusFor expires
+    expires: Option<Instant>,
+}
+
+impl PushedFocus {
+    fn new(application: String, window: String, pid: u32) -> PushedFocus {
+        PushedFocus {
+            application: Some(application).filter(|application| !application.is_empty()),
+            window: Some(window).filter(|window| !window.is_empty()),
+            properties: WindowProperties {
+                pid: Some(pid).filter(|pid| *pid > 0),
+                ..Default::default()
+            },
+            sender: None,
+            expires: None,
+        }
+    }
+}
+
+// The latest focus pushed to the service
+pub type Pushes = Arc<Mutex<Option<PushedFocus>>>;
+
+// The pushed focus unless it has expired or been forgotten
+pub fn fresh_push(pushes: &Pushes) -> Option<PushedFocus> {
+    let mut pushed = pushes.lock().unwrap();
+    if pushed
+        .as_ref()
+        .is_some_and(|focus| focus.expires.is_some_and(|expires| expires <= Instant::now()))
+    {
+        *pushed = None;
+    }
+    pushed.clone()
+}
+
+pub fn start_push_service() -> Pushes {
+    let pushes: Pushes = Arc::new(Mutex::new(None));
+    let shared = pushes.clone();
+    thread::spawn(move || {
+        if let Err(e) = serve(&shared) {
+            println!("D-Bus service {SERVICE_NAME} is unavailable: {e}");
+        }
+    });
+    pushes
+}
+
+fn serve(pushes: &Pushes) -> anyhow::Result<()> {
+    let connection = Connection::new_session()?;
+    let dbus = fdo::DBusProxy::new(&connection)?;
+    // Another xremap, e.g. of another seat, may have it already
+    let reply = dbus.request_name(SERVICE_NAME, fdo::RequestNameFlags::DoNotQueue.into())?;
+    if reply != fdo::RequestNameReply::PrimaryOwner {
+        anyhow::bail!("the name is already owned");
+    }
+    dbus.add_match(
+        "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameOwnerChanged'",
+    )?;
+
+    let mut object_server = ObjectServer::new(&connection);
+    let interface = FocusInterface { pushes: pushes.clone() };
+    object_server.at(&OBJECT_PATH.try_into()?, interface)?;
+    loop {
+        if let Some(message) = object_server.try_handle_next()? {
+            forget_disconnected(pushes, &message);
+        }
+    }
+}
+
+// Pushed data isn't fresh anymore once its sender has gone
+fn forget_disconnected(pushes: &Pushes, message: &Message) {
+    let is_name_owner_changed = message
+        .header()
+        .is_ok_and(|header| header.member().ok().flatten() == Some("NameOwnerChanged"));
+    if !is_name_owner_changed {
+        return;
+    }
+    if let Ok((name, _, new_owner)) = message.body::<(String, String, String)>() {
+        let mut pushed = pushes.lock().unwrap();
+        if new_owner.is_empty()
+            && pushed
+                .as_ref()
+                .is_some_and(|focus| focus.sender.as_ref() == Some(&name))
+        {
+            *pushed = None;
+        }
+    }
+}
+
+struct FocusInterface {
+    pushes: Pushes,
+}
+
+#[dbus_interface(name = "com.k0kubun.Xremap.Focus")]
+impl FocusInterface {
+    fn notify_focus(
+        &mut self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        application: String,
+        window: String,
+        pid: u32,
+    ) {
+        let sender = header.sender().ok().flatten().map(String::from);
+        *self.pushes.lock().unwrap() = Some(PushedFocus {
+            sender,
+            ..PushedFocus::new(application, window, pid)
+        });
+    }
+
+    fn notify_focus_for(&mut self, application: String, window: String, pid: u32, seconds: u32) {
+        let expires = Some(seconds)
+            .filter(|seconds| *seconds > 0)
+            .map(|seconds| Instant::now() + Duration::from_secs(seconds.into()));
+        *self.pushes.lock().unwrap() = Some(PushedFocus {
+            expires,
+            ..PushedFocus::new(application, window, pid)
+        });
+    }
+
+    // Arguments are positional over D-Bus, matching kwin-script.js. KWin passes JavaScript numbers as i.
+    #[allow(clippy::too_many_arguments)]
+    fn notify_kwin_window(
+        &mut self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        caption: String,
+        res_class: String,
+        res_name: String,
+        pid: i32,
+        desktop: String,
+        output: String,
+        fullscreen: bool,
+    ) {
+        // I want to always print this, since it is the only way to know what the resource class of applications is.
+        println!("active window: caption: '{caption}', class: '{res_class}', name: '{res_name}'");
+        let properties = WindowProperties {
+            pid: u32::try_from(pid).ok().filter(|pid| *pid > 0),
+            workspace: Some(desktop).filter(|desktop| !desktop.is_empty()),
+            output: Some(output).filter(|output| !output.is_empty()),
+            fullscreen: Some(fullscreen),
+            resource_name: Some(res_name).filter(|name| !name.is_empty()),
+            resource_class: Some(res_class.clone()).filter(|class| !class.is_empty()),
+            ..Default::default()
+        };
+        // Forgotten when KWin leaves the bus, until the reloaded script pushes again
+        let sender = header.sender().ok().flatten().map(String::from);
+        *self.pushes.lock().unwrap() = Some(PushedFocus {
+            properties,
+            sender,
+            ..PushedFocus::new(res_class, caption, 0)
+        });
+    }
+
+    fn clear_focus(&mut self) {
+        *self.pushes.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forget_disconnected() {
+        let name_owner_changed = |name: &str, new_owner: &str| {
+            Message::signal(
+                Some("org.freedesktop.DBus"),
+                None,
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "NameOwnerChanged",
+                &(name, name, new_owner),
+            )
+            .unwrap()
+        };
+        let pushes: Pushes = Arc::new(Mutex::new(Some(PushedFocus {
+            sender: Some(String::from(":1.5")),
+            ..PushedFocus::new(String::from("firefox"), String::new(), 0)
+        })));
+
+        forget_disconnected(&pushes, &name_owner_changed(":1.6", ""));
+        assert!(pushes.lock().unwrap().is_some());
+        forget_disconnected(&pushes, &name_owner_changed(":1.5", ""));
+        assert!(pushes.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_notify_kwin_window() {
+        let message =
+            Message::method(Some(":1.5"), Some(SERVICE_NAME), OBJECT_PATH, Some(SERVICE_NAME), "NotifyKWinWindow", &())
+                .unwrap();
+        let pushes: Pushes = Arc::new(Mutex::new(None));
+        let mut interface = FocusInterface { pushes: pushes.clone() };
+        interface.notify_kwin_window(
+            message.header().unwrap(),
+            String::from("~ : bash"),
+            String::from("org.kde.konsole"),
+            String::from("konsole"),
+            1234,
+            String::from("Desktop 1"),
+            String::new(),
+            false,
+        );
+
+        let pushed = fresh_push(&pushes).unwrap();
+        assert_eq!(Some("org.kde.konsole"), pushed.application.as_deref());
+        assert_eq!(Some("~ : bash"), pushed.window.as_deref());
+        assert_eq!(Some(":1.5"), pushed.sender.as_deref());
+        assert_eq!(
+            WindowProperties {
+                pid: Some(1234),
+                workspace: Some(String::from("Desktop 1")),
+                fullscreen: Some(false),
+                resource_name: Some(String::from("konsole")),
+                resource_class: Some(String::from("org.kde.konsole")),
+                ..Default::default()
+            },
+            pushed.properties
+        );
+    }
+
+    #[test]
+    fn test_fresh_push() {
+        let pushes: Pushes = Arc::new(Mutex::new(Some(PushedFocus::new(String::from("foot"), String::new(), 0))));
+        assert_eq!(Some(String::from("foot")), fresh_push(&pushes).and_then(|focus| focus.application));
+
+        pushes.lock().unwrap().as_mut().unwrap().expires = Some(Instant::now());
+        assert_eq!(None, fresh_push(&pushes));
+        assert!(pushes.lock().unwrap().is_none());
+    }
+}
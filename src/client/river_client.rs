@@ -0,0 +1,79 @@
+use anyhow::Result;
+use wayland_client::{globals::registry_queue_init, protocol::wl_seat::WlSeat, Connection, EventQueue};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1;
+
+use crate::client::river_status::zriver_status_manager_v1::ZriverStatusManagerV1;
+use crate::client::wayland_toplevels::Toplevels;
+use crate::client::Client;
+
+#[derive(Default)]
+pub struct RiverClient {
+    queue: Option<EventQueue<Toplevels>>,
+    state: Toplevels,
+}
+
+impl RiverClient {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        let connection = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<Toplevels>(&connection)?;
+        let handle = queue.handle();
+
+        let manager = globals.bind::<ZriverStatusManagerV1, _, _>(&handle, 1..=4, ())?;
+        let seat = globals.bind::<WlSeat, _, _>(&handle, 1..=7, ())?;
+        manager.get_river_seat_status(&seat, &handle, ());
+        // river-status only tells the title of the focused view. app_id needs the toplevel list.
+        if globals
+            .bind::<ExtForeignToplevelListV1, _, _>(&handle, 1..=1, ())
+            .is_err()
+        {
+            println!("RiverClient: ext_foreign_toplevel_list_v1 is not supported. Only window titles are available.");
+        }
+
+        self.state = Toplevels::default();
+        queue.roundtrip(&mut self.state)?;
+        self.queue = Some(queue);
+        Ok(())
+    }
+
+    fn roundtrip(&mut self) -> Option<()> {
+        let queue = self.queue.as_mut()?;
+
+        if queue.roundtrip(&mut self.state).is_err() {
+            // river may have restarted
+            if let Err(err) = self.connect() {
+                log::error!("{err}");
+                return None;
+            }
+
+            log::debug!("Reconnected to river");
+        }
+        Some(())
+    }
+}
+
+impl Client for RiverClient {
+    fn supported(&mut self) -> bool {
+        match self.connect() {
+            Ok(_) => true,
+            Err(e) => {
+                println!("RiverClient#connect() failed: {e}");
+                false
+            }
+        }
+    }
+
+    fn current_window(&mut self) -> Option<String> {
+        self.roundtrip()?;
+        self.state.focused_title.clone()
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        self.roundtrip()?;
+        let id = self.state.focused_by_title()?;
+        self.state.applications.get(id).cloned()
+    }
+}
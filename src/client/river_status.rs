@@ -0,0 +1,13 @@
+// Bindings of river-status-unstable-v1, which no protocol crate provides
+#![allow(dead_code, non_camel_case_types, non_upper_case_globals, unused_imports, clippy::all)]
+
+use wayland_client;
+use wayland_client::protocol::*;
+
+pub mod __interfaces {
+    use wayland_client::protocol::__interfaces::*;
+    wayland_scanner::generate_interfaces!("protocols/river-status-unstable-v1.xml");
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_client_code!("protocols/river-status-unstable-v1.xml");
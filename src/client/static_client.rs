@@ -0,0 +1,25 @@
+use crate::client::{Client, WindowProperties};
+
+// A client that always reports the same window. Used by `xremap simulate` and tests.
+pub struct StaticClient {
+    pub current_application: Option<String>,
+    pub current_window: Option<String>,
+    pub window_properties: WindowProperties,
+}
+
+impl Client for StaticClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        self.current_window.clone()
+    }
+
+    fn current_application(&mut self) -> Option<String> {
+        self.current_application.clone()
+    }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        self.window_properties.clone()
+    }
+}
@@ -1,16 +1,35 @@
-use crate::client::Client;
+use crate::client::{Client, WindowProperties};
 use std::env;
 use std::fs::read_dir;
 use std::os::unix::ffi::OsStrExt;
-use swayipc::Connection;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use swayipc::{Connection, Event, EventType, Node, NodeType, ShellType, WindowChange, WindowEvent};
 
 pub struct SwayClient {
     connection: Option<Connection>,
+    // Kept up to date by the subscription thread. None while it's not subscribed.
+    focus: Arc<Mutex<Option<Focus>>>,
+    subscribed: bool,
+}
+
+// The focused window
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Focus {
+    application: Option<String>,
+    window: Option<String>,
+    properties: WindowProperties,
 }
 
 impl SwayClient {
     pub fn new() -> SwayClient {
-        SwayClient { connection: None }
+        SwayClient {
+            connection: None,
+            focus: Arc::new(Mutex::new(None)),
+            subscribed: false,
+        }
     }
 
     fn connect(&mut self) {
@@ -19,11 +38,11 @@ impl SwayClient {
                 let path = match find_socket() {
                     Some(path) => path,
                     None => {
-                        println!("Failed to locate a SWAYSOCK from /run/user/1000/sway-ipc.*");
+                        println!("Failed to locate a SWAYSOCK from $SUDO_UID, $XDG_RUNTIME_DIR or /run/user/*");
                         return;
                     }
                 };
-                println!("$SWAYSOCK is not set. Defaulting to \"{}\"", path);
+                println!("$SWAYSOCK is not set. Defaulting to \"{}\"", path.display());
                 env::set_var("SWAYSOCK", path);
             }
 
@@ -32,6 +51,22 @@ impl SwayClient {
                 Err(e) => println!("SwayClient#connect() failed: {}", e),
             }
         }
+        if self.connection.is_some() && !self.subscribed {
+            subscribe(self.focus.clone());
+            self.subscribed = true;
+        }
+    }
+
+    // Get the focus from the subscription, or fetch the tree if it's not available
+    fn focus(&mut self) -> Focus {
+        if let Some(focus) = self.focus.lock().unwrap().clone() {
+            return focus;
+        }
+        self.connect();
+        match self.connection.as_mut().map(Connection::get_tree) {
+            Some(Ok(tree)) => focus_from_tree(&tree),
+            _ => Focus::default(),
+        }
     }
 }
 
@@ -40,45 +75,216 @@ impl Client for SwayClient {
         self.connect();
         self.connection.is_some()
     }
+
     fn current_window(&mut self) -> Option<String> {
-        // TODO:  not implemented
-        None
+        self.focus().window
     }
 
     fn current_application(&mut self) -> Option<String> {
-        self.connect();
-        let connection = match &mut self.connection {
-            Some(connection) => connection,
-            None => return None,
-        };
+        self.focus().application
+    }
 
-        if let Ok(node) = connection.get_tree() {
-            if let Some(node) = node.find_focused(|n| n.focused) {
-                if node.app_id.is_some() {
-                    return node.app_id;
-                } else if let Some(wp) = node.window_properties {
-                    return wp.class;
+    fn window_properties(&mut self) -> WindowProperties {
+        self.focus().properties
+    }
+}
+
+// Follow window and workspace events on a thread, instead of fetching the tree on every key event.
+// The thread reconnects when Sway restarts.
+fn subscribe(focus: Arc<Mutex<Option<Focus>>>) {
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            if let Err(e) = watch_focus(&focus) {
+                if !reported {
+                    println!("SwayClient failed to subscribe to events, retrying quietly: {e}");
+                    reported = true;
                 }
             }
+            *focus.lock().unwrap() = None;
+            thread::sleep(Duration::from_secs(1));
         }
-        None
-    }
+    });
 }
 
-// e.g. "/run/user/1000/sway-ipc.1000.2575.sock"
-fn find_socket() -> Option<String> {
-    let uid = 1000; // Assume a first nornal Linux user. TODO: Make it configurable
-    if let Some(run_user) = read_dir(format!("/run/user/{}", uid)).as_mut().ok() {
-        while let Some(entry) = run_user.next() {
-            let path = entry.ok()?.path();
-            if let Some(fname) = path.file_name() {
-                if fname.as_bytes().starts_with(b"sway-ipc.") {
-                    if let Ok(path) = path.into_os_string().into_string() {
-                        return Some(path);
-                    }
-                }
+fn watch_focus(focus: &Mutex<Option<Focus>>) -> Result<(), swayipc::Error> {
+    let mut connection = Connection::new()?;
+    let events = Connection::new()?.subscribe([EventType::Window, EventType::Workspace])?;
+    *focus.lock().unwrap() = Some(focus_from_tree(&connection.get_tree()?));
+    for event in events {
+        match event? {
+            Event::Window(event) if event.change == WindowChange::Title => {
+                update_title(&mut focus.lock().unwrap(), *event);
             }
+            Event::Window(_) | Event::Workspace(_) => {
+                *focus.lock().unwrap() = Some(focus_from_tree(&connection.get_tree()?));
+            }
+            _ => {}
         }
     }
-    None
+    Ok(())
+}
+
+// Titles change often, e.g. in terminals. They don't need the tree.
+fn update_title(focus: &mut Option<Focus>, event: WindowEvent) {
+    if let Some(focus) = focus.as_mut().filter(|_| event.container.focused) {
+        focus.window = event.container.name;
+    }
+}
+
+fn focus_from_tree(tree: &Node) -> Focus {
+    // An empty workspace may be focused instead of a view
+    let node = match tree.find_focused_as_ref(|n| n.focused) {
+        Some(node) if matches!(node.node_type, NodeType::Con | NodeType::FloatingCon) => node,
+        _ => return Focus::default(),
+    };
+    let application = match &node.app_id {
+        Some(app_id) => Some(app_id.clone()),
+        None => node.window_properties.as_ref().and_then(|wp| wp.class.clone()),
+    };
+    Focus {
+        application,
+        window: node.name.clone(),
+        properties: focused_properties(tree, None, None).unwrap_or_default(),
+    }
+}
+
+// Find the focused view, keeping track of the output and workspace it's on
+fn focused_properties(node: &Node, output: Option<&str>, workspace: Option<&str>) -> Option<WindowProperties> {
+    let name = node.name.as_deref();
+    let output = if node.node_type == NodeType::Output {
+        name
+    } else {
+        output
+    };
+    let workspace = if node.node_type == NodeType::Workspace {
+        name
+    } else {
+        workspace
+    };
+    if node.focused && matches!(node.node_type, NodeType::Con | NodeType::FloatingCon) {
+        return Some(WindowProperties {
+            pid: node.pid.and_then(|pid| u32::try_from(pid).ok()),
+            executable: None,
+            workspace: workspace.map(String::from),
+            output: output.map(String::from),
+            floating: Some(node.node_type == NodeType::FloatingCon),
+            fullscreen: node.fullscreen_mode.map(|mode| mode != 0),
+            xwayland: node.shell.map(|shell| shell == ShellType::Xwayland),
+            ..Default::default()
+        });
+    }
+    node.nodes
+        .iter()
+        .chain(node.floating_nodes.iter())
+        .find_map(|child| focused_properties(child, output, workspace))
+}
+
+// e.g. "/run/user/1000/sway-ipc.1000.2575.sock"
+// Look in the runtime directory of the user who ran sudo, then our own, then any user's.
+fn find_socket() -> Option<PathBuf> {
+    let mut dirs: Vec<PathBuf> = vec![];
+    if let Ok(uid) = env::var("SUDO_UID") {
+        dirs.push(PathBuf::from(format!("/run/user/{uid}")));
+    }
+    if let Ok(dir) = env::var("XDG_RUNTIME_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Ok(entries) = read_dir("/run/user") {
+        let mut user_dirs: Vec<PathBuf> = entries.filter_map(|entry| Some(entry.ok()?.path())).collect();
+        user_dirs.sort();
+        dirs.extend(user_dirs);
+    }
+    dirs.iter().find_map(|dir| find_socket_in(dir))
+}
+
+// The newest socket, since a crashed Sway may have left an old one
+fn find_socket_in(dir: &Path) -> Option<PathBuf> {
+    read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().as_bytes().starts_with(b"sway-ipc."))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max()
+        .map(|(_, path)| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // A node of get_tree's reply with the given fields
+    fn node(id: i64, node_type: &str, name: &str, fields: Value) -> Value {
+        let rect = json!({ "x": 0, "y": 0, "width": 0, "height": 0 });
+        let mut node = json!({
+            "id": id, "name": name, "type": node_type, "border": "none", "current_border_width": 0,
+            "layout": "splith", "percent": null, "rect": rect, "window_rect": rect, "deco_rect": rect,
+            "geometry": rect, "urgent": false, "focused": false, "focus": [], "nodes": [], "floating_nodes": [],
+            "sticky": false,
+        });
+        node.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        node
+    }
+
+    // foot is focused next to firefox
+    fn tree() -> Node {
+        let foot = node(
+            4,
+            "con",
+            "~/src",
+            json!({ "focused": true, "app_id": "foot", "pid": 1234, "fullscreen_mode": 1, "shell": "xdg_shell" }),
+        );
+        let firefox = node(5, "con", "Mozilla Firefox", json!({ "app_id": "firefox", "pid": 5678 }));
+        let workspace = node(3, "workspace", "2", json!({ "nodes": [firefox, foot], "focus": [4, 5] }));
+        let output = node(2, "output", "DP-1", json!({ "nodes": [workspace], "focus": [3] }));
+        serde_json::from_value(node(1, "root", "root", json!({ "nodes": [output], "focus": [2] }))).unwrap()
+    }
+
+    #[test]
+    fn test_focus_from_tree() {
+        let focus = focus_from_tree(&tree());
+        assert_eq!(Some("foot"), focus.application.as_deref());
+        assert_eq!(Some("~/src"), focus.window.as_deref());
+        assert_eq!(
+            WindowProperties {
+                pid: Some(1234),
+                workspace: Some(String::from("2")),
+                output: Some(String::from("DP-1")),
+                floating: Some(false),
+                fullscreen: Some(true),
+                xwayland: Some(false),
+                ..Default::default()
+            },
+            focus.properties
+        );
+
+        // An empty workspace
+        let workspace = node(2, "workspace", "3", json!({ "focused": true }));
+        let tree =
+            serde_json::from_value(node(1, "root", "root", json!({ "nodes": [workspace], "focus": [2] }))).unwrap();
+        assert_eq!(Focus::default(), focus_from_tree(&tree));
+    }
+
+    #[test]
+    fn test_update_title() {
+        let title_event = |focused: bool, title: &str| {
+            let container = node(4, "con", title, json!({ "focused": focused, "app_id": "foot" }));
+            serde_json::from_value::<WindowEvent>(json!({ "change": "title", "container": container })).unwrap()
+        };
+        let mut focus = Some(focus_from_tree(&tree()));
+
+        update_title(&mut focus, title_event(true, "vim"));
+        assert_eq!(Some("vim"), focus.as_ref().and_then(|focus| focus.window.as_deref()));
+        assert_eq!(Some(1234), focus.as_ref().and_then(|focus| focus.properties.pid));
+        // Titles of other windows are ignored
+        update_title(&mut focus, title_event(false, "htop"));
+        assert_eq!(Some("vim"), focus.as_ref().and_then(|focus| focus.window.as_deref()));
+        // and so is a title before the subscription has the focus
+        let mut focus = None;
+        update_title(&mut focus, title_event(true, "vim"));
+        assert_eq!(None, focus);
+    }
 }
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use wayland_client::{
+    backend::ObjectId,
+    event_created_child,
+    globals::GlobalListContents,
+    protocol::{wl_registry, wl_seat::WlSeat},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::{
+    ext_foreign_toplevel_handle_v1::{Event as HandleEvent, ExtForeignToplevelHandleV1},
+    ext_foreign_toplevel_list_v1::{Event as ListEvent, ExtForeignToplevelListV1},
+};
+
+use crate::client::river_status::{
+    zriver_seat_status_v1::{Event as SeatStatusEvent, ZriverSeatStatusV1},
+    zriver_status_manager_v1::ZriverStatusManagerV1,
+};
+
+// Toplevels of ext_foreign_toplevel_list_v1 or zwlr_foreign_toplevel_manager_v1, and river-status's focused view.
+// The wlroots client and the river client dispatch Wayland events to it.
+#[derive(Default, Debug)]
+pub struct Toplevels {
+    // The activated toplevel of zwlr_foreign_toplevel_handle_v1, which only the wlroots client binds
+    #[cfg(feature = "wlroots")]
+    pub active_window: Option<ObjectId>,
+    pub applications: HashMap<ObjectId, String>,
+    pub titles: HashMap<ObjectId, String>,
+    // The title of river-status's focused_view. None if no view is focused.
+    pub focused_title: Option<String>,
+}
+
+impl Toplevels {
+    pub fn add(&mut self, id: ObjectId) {
+        self.applications.insert(id.clone(), "<unknown>".into());
+        self.titles.insert(id, "<unknown>".into());
+    }
+
+    pub fn remove(&mut self, id: &ObjectId) {
+        self.applications.remove(id);
+        self.titles.remove(id);
+    }
+
+    // The toplevel of river-status's focused view. river-status tells only its title,
+    // so it's unknown while another toplevel has the same title.
+    pub fn focused_by_title(&self) -> Option<&ObjectId> {
+        find_title(&self.titles, self.focused_title.as_ref()?)
+    }
+}
+
+// The only toplevel with the title. None if there are many, rather than an arbitrary one of them.
+fn find_title<'a, K>(titles: &'a HashMap<K, String>, title: &str) -> Option<&'a K> {
+    let mut ids = titles.iter().filter(|(_, t)| *t == title).map(|(id, _)| id);
+    match (ids.next(), ids.next()) {
+        (Some(id), None) => Some(id),
+        _ => None,
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Toplevels {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        log::trace!("{event:?}");
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for Toplevels {
+    fn event(
+        state: &mut Self,
+        _: &ExtForeignToplevelListV1,
+        event: ListEvent,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let ListEvent::Toplevel { toplevel } = event {
+            state.add(toplevel.id());
+        }
+    }
+
+    event_created_child!(Toplevels, ExtForeignToplevelListV1, [
+        _ => (ExtForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for Toplevels {
+    fn event(
+        state: &mut Self,
+        handle: &ExtForeignToplevelHandleV1,
+        event: HandleEvent,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            HandleEvent::AppId { app_id } => {
+                state.applications.insert(handle.id(), app_id);
+            }
+            HandleEvent::Title { title } => {
+                state.titles.insert(handle.id(), title);
+            }
+            HandleEvent::Closed => state.remove(&handle.id()),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZriverStatusManagerV1, ()> for Toplevels {
+    fn event(
+        _: &mut Self,
+        _: &ZriverStatusManagerV1,
+        _: <ZriverStatusManagerV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZriverSeatStatusV1, ()> for Toplevels {
+    fn event(
+        state: &mut Self,
+        _: &ZriverSeatStatusV1,
+        event: SeatStatusEvent,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let SeatStatusEvent::FocusedView { title } = event {
+            // An empty title means that no view is focused
+            state.focused_title = Some(title).filter(|title| !title.is_empty());
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Toplevels {
+    fn event(_: &mut Self, _: &WlSeat, _: <WlSeat as Proxy>::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_title() {
+        let titles = HashMap::from([
+            (1, String::from("~/src")),
+            (2, String::from("Mozilla Firefox")),
+            (3, String::from("~")),
+            (4, String::from("~")),
+        ]);
+        assert_eq!(Some(&2), find_title(&titles, "Mozilla Firefox"));
+        assert_eq!(None, find_title(&titles, "Mozilla"));
+        // e.g. foot and Alacritty in the home directory
+        assert_eq!(None, find_title(&titles, "~"));
+    }
+}
@@ -1,32 +1,38 @@
-use std::collections::HashMap;
-
-use anyhow::{Context, Result};
+use anyhow::{bail, Result};
 use wayland_client::{
-    backend::ObjectId,
+    backend::{protocol::Interface, ObjectId},
     event_created_child,
-    globals::{registry_queue_init, GlobalListContents},
-    protocol::wl_registry,
+    globals::{registry_queue_init, GlobalList},
+    protocol::wl_seat::WlSeat,
     Connection, Dispatch, EventQueue, Proxy, QueueHandle,
 };
 
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1;
 use wayland_protocols_wlr::foreign_toplevel::v1::client::{
     zwlr_foreign_toplevel_handle_v1::{Event as HandleEvent, State as HandleState, ZwlrForeignToplevelHandleV1},
     zwlr_foreign_toplevel_manager_v1::{Event as ManagerEvent, ZwlrForeignToplevelManagerV1},
 };
 
-use crate::client::Client;
-
-#[derive(Default, Debug)]
-struct State {
-    active_window: Option<ObjectId>,
-    windows: HashMap<ObjectId, String>,
-    titles: HashMap<ObjectId, String>,
+use crate::client::river_status::zriver_status_manager_v1::ZriverStatusManagerV1;
+use crate::client::wayland_toplevels::Toplevels;
+use crate::client::{niri_ipc, Client, WindowProperties};
+
+// Where the active window comes from, in the order of preference
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Focus {
+    // zwlr_foreign_toplevel_handle_v1's activated state
+    Activated,
+    // ext_foreign_toplevel_list_v1 has no activation state, so ask niri's IPC
+    Niri,
+    // river-status's focused_view, matched with a toplevel of ext_foreign_toplevel_list_v1 by its title
+    RiverStatus,
 }
 
 #[derive(Default)]
 pub struct WlRootsClient {
-    queue: Option<EventQueue<State>>,
-    state: State,
+    queue: Option<EventQueue<Toplevels>>,
+    state: Toplevels,
+    focus: Option<Focus>,
 }
 
 impl WlRootsClient {
@@ -36,11 +42,11 @@ impl WlRootsClient {
 
     fn connect(&mut self) -> Result<()> {
         let connection = Connection::connect_to_env()?;
-        let (globals, mut queue) = registry_queue_init::<State>(&connection)?;
+        let (globals, mut queue) = registry_queue_init::<Toplevels>(&connection)?;
 
-        globals
-            .bind::<ZwlrForeignToplevelManagerV1, _, _>(&queue.handle(), 1..=3, ())
-            .context("wlr_foreign_toplevel_management_unstable_v1 protocol is not supported")?;
+        self.state = Toplevels::default();
+        self.focus = Some(negotiate(&globals, &queue.handle())?);
+        log::debug!("wlroots client uses {:?}", self.focus);
 
         queue.roundtrip(&mut self.state)?;
 
@@ -48,6 +54,74 @@ impl WlRootsClient {
 
         Ok(())
     }
+
+    fn roundtrip(&mut self) -> Option<()> {
+        let queue = self.queue.as_mut()?;
+
+        if queue.roundtrip(&mut self.state).is_err() {
+            // try to reconnect
+            if let Err(err) = self.connect() {
+                log::error!("{err}");
+                return None;
+            }
+
+            log::debug!("Reconnected to wayland");
+        }
+        Some(())
+    }
+
+    // The ID of the active toplevel, if the protocols tell it
+    fn active_window(&self) -> Option<&ObjectId> {
+        match self.focus? {
+            Focus::Activated => self.state.active_window.as_ref(),
+            Focus::RiverStatus => self.state.focused_by_title(),
+            Focus::Niri => None,
+        }
+    }
+}
+
+// Bind the first toplevel protocol the compositor supports, with what tells the active window
+fn negotiate(globals: &GlobalList, handle: &QueueHandle<Toplevels>) -> Result<Focus> {
+    let interfaces: Vec<String> = globals
+        .contents()
+        .with_list(|list| list.iter().map(|global| global.interface.clone()).collect());
+    let focus = choose_focus(&interfaces, niri_ipc::is_available())?;
+    match focus {
+        Focus::Activated => {
+            globals.bind::<ZwlrForeignToplevelManagerV1, _, _>(handle, 1..=3, ())?;
+        }
+        // niri's IPC tells everything
+        Focus::Niri => {}
+        Focus::RiverStatus => {
+            globals.bind::<ExtForeignToplevelListV1, _, _>(handle, 1..=1, ())?;
+            let manager = globals.bind::<ZriverStatusManagerV1, _, _>(handle, 1..=4, ())?;
+            let seat = globals.bind::<WlSeat, _, _>(handle, 1..=7, ())?;
+            manager.get_river_seat_status(&seat, handle, ());
+        }
+    }
+    Ok(focus)
+}
+
+// Choose where the active window comes from by the interfaces of the compositor's globals
+fn choose_focus(interfaces: &[String], niri_available: bool) -> Result<Focus> {
+    let supports = |interface: &Interface| interfaces.iter().any(|name| name == interface.name);
+    if supports(ZwlrForeignToplevelManagerV1::interface()) {
+        return Ok(Focus::Activated);
+    }
+    if niri_available {
+        return Ok(Focus::Niri);
+    }
+    // COSMIC tells the active window only through its own protocol
+    if interfaces.iter().any(|name| name == "zcosmic_toplevel_info_v1") {
+        bail!("COSMIC's zcosmic_toplevel_info_v1 protocol isn't supported. Use --focus-command, or push the focus to the com.k0kubun.Xremap.Focus D-Bus service");
+    }
+    if !supports(ExtForeignToplevelListV1::interface()) {
+        bail!("Neither wlr_foreign_toplevel_management_unstable_v1 nor ext_foreign_toplevel_list_v1 protocol is supported");
+    }
+    if supports(ZriverStatusManagerV1::interface()) {
+        return Ok(Focus::RiverStatus);
+    }
+    bail!("ext_foreign_toplevel_list_v1 protocol doesn't tell the active window, and neither niri's IPC nor river-status is available");
 }
 
 impl Client for WlRootsClient {
@@ -61,74 +135,58 @@ impl Client for WlRootsClient {
         }
     }
     fn current_window(&mut self) -> Option<String> {
-        let queue = self.queue.as_mut()?;
-
-        if let Err(_) = queue.roundtrip(&mut self.state) {
-            // try to reconnect
-            if let Err(err) = self.connect() {
-                log::error!("{err}");
-                return None;
-            }
-
-            log::debug!("Reconnected to wayland");
+        if self.focus == Some(Focus::Niri) {
+            return niri_ipc::focused_window_or_log()?.title;
         }
-
-        let id = self.state.active_window.as_ref()?;
+        self.roundtrip()?;
+        let id = self.active_window()?;
         self.state.titles.get(id).cloned()
     }
 
     fn current_application(&mut self) -> Option<String> {
-        let queue = self.queue.as_mut()?;
-
-        if let Err(_) = queue.roundtrip(&mut self.state) {
-            // try to reconnect
-            if let Err(err) = self.connect() {
-                log::error!("{err}");
-                return None;
-            }
-
-            log::debug!("Reconnected to wayland");
+        if self.focus == Some(Focus::Niri) {
+            return niri_ipc::focused_window_or_log()?.app_id;
         }
-
-        let id = self.state.active_window.as_ref()?;
-        self.state.windows.get(id).cloned()
+        self.roundtrip()?;
+        let id = self.active_window()?;
+        self.state.applications.get(id).cloned()
     }
-}
 
-impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
-    fn event(
-        _state: &mut Self,
-        _registry: &wl_registry::WlRegistry,
-        event: wl_registry::Event,
-        _: &GlobalListContents,
-        _connection: &Connection,
-        _: &QueueHandle<State>,
-    ) {
-        log::trace!("{event:?}");
+    fn window_properties(&mut self) -> WindowProperties {
+        match self.focus {
+            Some(Focus::Niri) => match niri_ipc::focused_window_or_log() {
+                Some(window) => WindowProperties {
+                    pid: window.pid,
+                    floating: window.is_floating,
+                    ..Default::default()
+                },
+                None => WindowProperties::default(),
+            },
+            _ => WindowProperties::default(),
+        }
     }
 }
 
-impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for State {
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Toplevels {
     fn event(
         state: &mut Self,
         _: &ZwlrForeignToplevelManagerV1,
         event: ManagerEvent,
         _: &(),
         _: &Connection,
-        _: &QueueHandle<State>,
+        _: &QueueHandle<Toplevels>,
     ) {
         if let ManagerEvent::Toplevel { toplevel } = event {
-            state.windows.insert(toplevel.id(), "<unknown>".into());
-            state.titles.insert(toplevel.id(), "<unknown>".into());
+            state.add(toplevel.id());
         }
     }
 
-    event_created_child!(State, ZwlrForeignToplevelManagerV1, [
+    event_created_child!(Toplevels, ZwlrForeignToplevelManagerV1, [
         _ => (ZwlrForeignToplevelHandleV1, ())
     ]);
 }
 
-impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Toplevels {
     fn event(
         state: &mut Self,
         handle: &ZwlrForeignToplevelHandleV1,
@@ -139,15 +197,12 @@ impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
     ) {
         match event {
             HandleEvent::AppId { app_id } => {
-                state.windows.insert(handle.id(), app_id);
+                state.applications.insert(handle.id(), app_id);
             }
             HandleEvent::Title { title } => {
                 state.titles.insert(handle.id(), title);
             }
-            HandleEvent::Closed => {
-                state.windows.remove(&handle.id());
-                state.titles.remove(&handle.id());
-            }
+            HandleEvent::Closed => state.remove(&handle.id()),
             HandleEvent::State { state: handle_state } => {
                 let activated = HandleState::Activated as u8;
                 if handle_state.contains(&activated) {
@@ -158,3 +213,33 @@ impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_focus() {
+        let globals = |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+        let wlr = globals(&[
+            "wl_seat",
+            "zwlr_foreign_toplevel_manager_v1",
+            "ext_foreign_toplevel_list_v1",
+        ]);
+        assert_eq!(Focus::Activated, choose_focus(&wlr, true).unwrap());
+
+        let ext = globals(&["wl_seat", "ext_foreign_toplevel_list_v1"]);
+        assert_eq!(Focus::Niri, choose_focus(&ext, true).unwrap());
+        assert!(choose_focus(&ext, false).is_err());
+        assert_eq!(Focus::Niri, choose_focus(&globals(&["wl_seat"]), true).unwrap());
+
+        let river = globals(&["wl_seat", "ext_foreign_toplevel_list_v1", "zriver_status_manager_v1"]);
+        assert_eq!(Focus::RiverStatus, choose_focus(&river, false).unwrap());
+        // river-status needs the toplevel list to find the window
+        assert!(choose_focus(&globals(&["wl_seat", "zriver_status_manager_v1"]), false).is_err());
+
+        let cosmic = globals(&["wl_seat", "ext_foreign_toplevel_list_v1", "zcosmic_toplevel_info_v1"]);
+        let err = choose_focus(&cosmic, false).unwrap_err().to_string();
+        assert!(err.contains("COSMIC") && err.contains("--focus-command"), "{err}");
+    }
+}
@@ -1,24 +1,60 @@
-use crate::client::Client;
-use anyhow::bail;
+use crate::client::{Client, WindowProperties};
 use std::env;
-use x11rb::cookie::Cookie;
-use x11rb::protocol::xproto::{self};
-use x11rb::protocol::xproto::{AtomEnum, Window};
-use x11rb::rust_connection::ConnectionError;
-use x11rb::x11_utils::TryParse;
-use x11rb::{protocol::xproto::get_property, rust_connection::RustConnection};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use x11rb::atom_manager;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, GetPropertyReply, Window,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_SUPPORTED,
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_DESKTOP,
+        _NET_WM_STATE,
+        _NET_WM_STATE_FULLSCREEN,
+        UTF8_STRING,
+    }
+}
 
 pub struct X11Client {
-    connection: Option<RustConnection>,
+    x11: Option<X11>,
+    // Kept up to date by the subscription thread. None while it's not subscribed.
+    focus: Arc<Mutex<Option<Focus>>>,
+    subscribed: bool,
+    // Whether a connection failure has been reported and not recovered yet
+    reported: bool,
+    last_attempt: Option<Instant>,
+}
+
+// The focused window
+#[derive(Clone, Debug, Default)]
+struct Focus {
+    application: Option<String>,
+    window: Option<String>,
+    properties: WindowProperties,
 }
 
 impl X11Client {
     pub fn new() -> X11Client {
-        X11Client { connection: None }
+        X11Client {
+            x11: None,
+            focus: Arc::new(Mutex::new(None)),
+            subscribed: false,
+            reported: false,
+            last_attempt: None,
+        }
     }
 
     fn connect(&mut self) {
-        if self.connection.is_some() {
+        if self.x11.is_some() {
             return;
         }
 
@@ -26,106 +62,293 @@ impl X11Client {
             println!("$DISPLAY is not set. Defaulting to DISPLAY=:0");
             env::set_var("DISPLAY", ":0");
         }
-        self.reconnect();
-    }
+        // Don't try to reconnect on every key press while the X server is gone
+        if self
+            .last_attempt
+            .is_some_and(|last| last.elapsed() < RECONNECT_INTERVAL)
+        {
+            return;
+        }
+        self.last_attempt = Some(Instant::now());
 
-    fn reconnect(&mut self) {
-        match x11rb::connect(None) {
-            Ok((connection, _)) => self.connection = Some(connection),
-            Err(error) => {
+        match X11::connect() {
+            Ok(x11) => {
+                if self.reported {
+                    println!("Reconnected to X11");
+                    self.reported = false;
+                }
+                if !self.subscribed && x11.supports_active_window() {
+                    subscribe(self.focus.clone());
+                    self.subscribed = true;
+                }
+                self.x11 = Some(x11);
+            }
+            Err(error) if !self.reported => {
                 let var = env::var("DISPLAY").unwrap();
-                println!("warning: Failed to connect to X11: {error}");
+                println!("warning: Failed to connect to X11, retrying quietly: {error}");
                 println!("If you saw \"No protocol specified\", try running `xhost +SI:localuser:root`.");
                 println!("If not, make sure `echo $DISPLAY` outputs xremap's $DISPLAY ({var}).");
+                self.reported = true;
             }
+            Err(_) => {}
         }
     }
+
+    // Get the focus from the subscription, or query it if it's not available
+    fn focus(&mut self) -> Focus {
+        if let Some(focus) = self.focus.lock().unwrap().clone() {
+            return focus;
+        }
+        self.connect();
+        let result = match &self.x11 {
+            Some(x11) => x11.focus(),
+            None => return Focus::default(),
+        };
+        result.unwrap_or_else(|error| {
+            if !self.reported {
+                println!("Lost the connection to X11, reconnecting quietly: {error}");
+                self.reported = true;
+            }
+            self.x11 = None;
+            Focus::default()
+        })
+    }
 }
 
 impl Client for X11Client {
     fn supported(&mut self) -> bool {
         self.connect();
-        return self.connection.is_some();
-        // TODO: Test XGetInputFocus and focused_window > 0?
+        self.x11.is_some()
     }
+
     fn current_window(&mut self) -> Option<String> {
-        // TODO:  not implemented
-        None
+        self.focus().window
     }
 
     fn current_application(&mut self) -> Option<String> {
-        self.connect();
-        let mut window = get_focus_window(self)?;
+        self.focus().application
+    }
+
+    fn window_properties(&mut self) -> WindowProperties {
+        self.focus().properties
+    }
+}
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Follow PropertyNotify of the root window on a thread, instead of querying the focus on every key event.
+// The thread reconnects when the X server restarts.
+fn subscribe(focus: Arc<Mutex<Option<Focus>>>) {
+    thread::spawn(move || {
+        let mut reported = false;
         loop {
-            if let Some(wm_class) = get_wm_class(self, window) {
-                // Workaround: https://github.com/JetBrains/jdk8u_jdk/blob/master/src/solaris/classes/sun/awt/X11/XFocusProxyWindow.java#L35
-                if &wm_class != "Focus-Proxy-Window.FocusProxy" {
-                    return Some(wm_class);
+            if let Err(e) = watch_focus(&focus) {
+                if !reported {
+                    println!("X11Client failed to watch the active window, retrying quietly: {e}");
+                    reported = true;
                 }
             }
+            *focus.lock().unwrap() = None;
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+    });
+}
+
+fn watch_focus(focus: &Mutex<Option<Focus>>) -> anyhow::Result<()> {
+    let x11 = X11::connect()?;
+    let property_change = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+    x11.connection
+        .change_window_attributes(x11.root, &property_change)?
+        .check()?;
+
+    let mut active_window = x11.active_window()?;
+    let mut refresh = true;
+    loop {
+        if refresh {
+            // Watch the title and the state of the new active window too
+            if let Some(window) = active_window {
+                x11.connection.change_window_attributes(window, &property_change)?;
+            }
+            *focus.lock().unwrap() = Some(x11.focus_of(active_window));
+            x11.connection.flush()?;
+        }
+        refresh = false;
 
-            window = get_parent_window(self, window)?;
+        // Errors for windows that have gone are just ignored
+        if let Event::PropertyNotify(event) = x11.connection.wait_for_event()? {
+            let atoms = &x11.atoms;
+            if event.window == x11.root && event.atom == atoms._NET_ACTIVE_WINDOW {
+                active_window = x11.active_window()?;
+                refresh = true;
+            } else if Some(event.window) == active_window {
+                if event.atom == atoms._NET_WM_NAME || event.atom == u32::from(AtomEnum::WM_NAME) {
+                    // Titles change often, e.g. in terminals
+                    if let Some(focus) = focus.lock().unwrap().as_mut() {
+                        focus.window = x11.title(event.window);
+                    }
+                } else if [
+                    atoms._NET_WM_STATE,
+                    atoms._NET_WM_DESKTOP,
+                    u32::from(AtomEnum::WM_CLASS),
+                ]
+                .contains(&event.atom)
+                {
+                    refresh = true;
+                }
+            }
         }
     }
 }
 
-fn get_focus_window(client: &mut X11Client) -> Option<Window> {
-    get_cookie_reply_with_reconnect(client, xproto::get_input_focus)
-        .map(|reply| reply.focus)
-        .ok()
+// A connection to the X server
+struct X11 {
+    connection: RustConnection,
+    root: Window,
+    atoms: Atoms,
 }
 
-fn get_parent_window(client: &mut X11Client, window: Window) -> Option<Window> {
-    get_cookie_reply_with_reconnect(client, |conn| xproto::query_tree(conn, window))
-        .map(|reply| reply.parent)
-        .ok()
-}
+impl X11 {
+    fn connect() -> anyhow::Result<X11> {
+        let (connection, screen) = x11rb::connect(None)?;
+        let root = connection.setup().roots[screen].root;
+        let atoms = Atoms::new(&connection)?.reply()?;
+        Ok(X11 {
+            connection,
+            root,
+            atoms,
+        })
+    }
 
-fn get_wm_class(client: &mut X11Client, window: Window) -> Option<String> {
-    let reply = get_cookie_reply_with_reconnect(client, |conn| {
-        get_property(conn, false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)
-    })
-    .ok()?;
+    // Whether the window manager supports EWMH's _NET_ACTIVE_WINDOW
+    fn supports_active_window(&self) -> bool {
+        self.property(self.root, self.atoms._NET_SUPPORTED, AtomEnum::ATOM, 1024)
+            .and_then(|reply| {
+                reply
+                    .value32()
+                    .map(|mut atoms| atoms.any(|atom| atom == self.atoms._NET_ACTIVE_WINDOW))
+            })
+            .unwrap_or(false)
+    }
 
-    if reply.value.is_empty() {
-        return None;
+    fn focus(&self) -> anyhow::Result<Focus> {
+        Ok(self.focus_of(self.active_window()?))
     }
 
-    if let Some(delimiter) = reply.value.iter().position(|byte| *byte == '\0' as u8) {
-        if let Ok(prefix) = String::from_utf8(reply.value[..delimiter].to_vec()) {
-            let name = reply.value[(delimiter + 1)..].to_vec();
-            if let Some(end) = name.iter().position(|byte| *byte == '\0' as u8) {
-                if end == name.len() - 1 {
-                    if let Ok(name) = String::from_utf8(name[..end].to_vec()) {
-                        return Some(format!("{prefix}.{name}"));
-                    }
+    fn focus_of(&self, window: Option<Window>) -> Focus {
+        let window = match window {
+            Some(window) => window,
+            None => return Focus::default(),
+        };
+        let wm_class = self.wm_class(window);
+        let fullscreen = self
+            .property(window, self.atoms._NET_WM_STATE, AtomEnum::ATOM, 1024)
+            .and_then(|reply| {
+                reply
+                    .value32()
+                    .map(|mut atoms| atoms.any(|atom| atom == self.atoms._NET_WM_STATE_FULLSCREEN))
+            });
+        Focus {
+            application: wm_class.as_ref().map(|(name, class)| format!("{name}.{class}")),
+            window: self.title(window),
+            properties: WindowProperties {
+                pid: self.cardinal(window, self.atoms._NET_WM_PID),
+                workspace: self
+                    .cardinal(window, self.atoms._NET_WM_DESKTOP)
+                    .map(|desktop| desktop.to_string()),
+                fullscreen,
+                resource_name: wm_class.as_ref().map(|(name, _)| name.clone()),
+                resource_class: wm_class.map(|(_, class)| class),
+                ..Default::default()
+            },
+        }
+    }
+
+    // _NET_ACTIVE_WINDOW if the window manager supports EWMH, or the window of the input focus with WM_CLASS
+    fn active_window(&self) -> anyhow::Result<Option<Window>> {
+        let active = self
+            .property(self.root, self.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, 1)
+            .and_then(|reply| reply.value32()?.next())
+            .filter(|window| *window != x11rb::NONE);
+        if active.is_some() {
+            return Ok(active);
+        }
+
+        let mut window = self.connection.get_input_focus()?.reply()?.focus;
+        loop {
+            if window == x11rb::NONE || window == self.root {
+                return Ok(None);
+            }
+            // Workaround: https://github.com/JetBrains/jdk8u_jdk/blob/master/src/solaris/classes/sun/awt/X11/XFocusProxyWindow.java#L35
+            match self.wm_class(window) {
+                Some((name, class)) if name != "Focus-Proxy-Window" || class != "FocusProxy" => {
+                    return Ok(Some(window))
                 }
+                _ => {}
             }
+            window = match self.connection.query_tree(window)?.reply() {
+                Ok(tree) => tree.parent,
+                Err(_) => return Ok(None),
+            };
         }
     }
-    return None;
+
+    // _NET_WM_NAME, or WM_NAME for windows without EWMH
+    fn title(&self, window: Window) -> Option<String> {
+        self.property(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING, 1024)
+            .filter(|reply| !reply.value.is_empty())
+            .or_else(|| self.property(window, AtomEnum::WM_NAME, AtomEnum::ANY, 1024))
+            .map(|reply| String::from_utf8_lossy(&reply.value).into_owned())
+    }
+
+    // WM_CLASS as (instance name, class name)
+    fn wm_class(&self, window: Window) -> Option<(String, String)> {
+        let reply = self.property(window, AtomEnum::WM_CLASS, AtomEnum::STRING, 1024)?;
+        parse_wm_class(&reply.value)
+    }
+
+    fn cardinal(&self, window: Window, property: Atom) -> Option<u32> {
+        self.property(window, property, AtomEnum::CARDINAL, 1)?
+            .value32()?
+            .next()
+    }
+
+    // None if the window has gone or doesn't have the property
+    fn property(
+        &self,
+        window: Window,
+        property: impl Into<Atom>,
+        type_: impl Into<Atom>,
+        long_length: u32,
+    ) -> Option<GetPropertyReply> {
+        self.connection
+            .get_property(false, window, property, type_, 0, long_length)
+            .ok()?
+            .reply()
+            .ok()
+            .filter(|reply| reply.type_ != x11rb::NONE)
+    }
 }
 
-fn get_cookie_reply_with_reconnect<T: TryParse>(
-    client: &mut X11Client,
-    get_cookie: impl Fn(&RustConnection) -> Result<Cookie<RustConnection, T>, ConnectionError>,
-) -> anyhow::Result<T> {
-    return match get_cookie_reply(client, &get_cookie) {
-        Err(e) => {
-            println!("Reconnecting to X11 due to error: {}", e);
-            client.reconnect();
-            get_cookie_reply(client, &get_cookie)
-        }
-        x => x,
-    };
+// Two null-terminated strings
+fn parse_wm_class(value: &[u8]) -> Option<(String, String)> {
+    let delimiter = value.iter().position(|byte| *byte == b'\0')?;
+    let name = String::from_utf8(value[..delimiter].to_vec()).ok()?;
+    let class = value[(delimiter + 1)..].strip_suffix(b"\0")?;
+    if class.contains(&b'\0') {
+        return None;
+    }
+    Some((name, String::from_utf8(class.to_vec()).ok()?))
 }
 
-fn get_cookie_reply<T: TryParse>(
-    client: &X11Client,
-    get_cookie: &impl Fn(&RustConnection) -> Result<Cookie<RustConnection, T>, ConnectionError>,
-) -> anyhow::Result<T> {
-    match &client.connection {
-        Some(conn) => return Ok(get_cookie(conn)?.reply()?),
-        None => bail!("No connection to X11"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wm_class() {
+        assert_eq!(Some((String::from("code"), String::from("Code"))), parse_wm_class(b"code\0Code\0"));
+        assert_eq!(None, parse_wm_class(b"code\0Code"));
+        assert_eq!(None, parse_wm_class(b"code"));
+        assert_eq!(None, parse_wm_class(b""));
     }
 }
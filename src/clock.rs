@@ -0,0 +1,117 @@
+use crate::event_handler::Timer;
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Time for EventHandler and ActionDispatcher: alone_timeout, the timeout of suspend, and delays
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+// The monotonic clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+// A clock that moves only when it sleeps or is advanced. Used by `xremap simulate` and tests.
+// Clones share the time.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<Instant>>,
+    // When the timer of nested remaps expires
+    timer: Rc<Cell<Option<Instant>>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Rc::new(Cell::new(Instant::now())),
+            timer: Rc::new(Cell::new(None)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+
+    // A Timer for EventHandler that expires on this clock
+    pub fn timer(&self) -> ManualTimer {
+        ManualTimer { clock: self.clone() }
+    }
+
+    // Whether the timer has expired. Like a one-shot TimerFd, it's unset once it expires.
+    pub fn take_expired_timer(&self) -> bool {
+        match self.timer.get() {
+            Some(expires_at) if expires_at <= self.now.get() => {
+                self.timer.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+pub struct ManualTimer {
+    clock: ManualClock,
+}
+
+impl Timer for ManualTimer {
+    fn set(&mut self, timeout: Duration) -> io::Result<()> {
+        self.clock.timer.set(Some(self.clock.now() + timeout));
+        Ok(())
+    }
+
+    fn unset(&mut self) -> io::Result<()> {
+        self.clock.timer.set(None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_timer() {
+        let clock = ManualClock::new();
+        let mut timer = clock.timer();
+        timer.set(Duration::from_millis(100)).unwrap();
+        clock.sleep(Duration::from_millis(99));
+        assert!(!clock.take_expired_timer());
+        clock.advance(Duration::from_millis(1));
+        assert!(clock.take_expired_timer());
+        assert!(!clock.take_expired_timer());
+
+        timer.set(Duration::from_millis(100)).unwrap();
+        timer.unset().unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert!(!clock.take_expired_timer());
+    }
+}
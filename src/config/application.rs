@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 
 use anyhow::anyhow;
@@ -5,7 +6,7 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer};
 
 // TODO: Use trait to allow only either `only` or `not`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct OnlyOrNot {
     #[serde(default, deserialize_with = "deserialize_matchers")]
@@ -14,14 +15,70 @@ pub struct OnlyOrNot {
     pub not: Option<Vec<ApplicationMatcher>>,
 }
 
+// application_modes: Switch to `mode` while a matching application is focused
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApplicationMode {
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    pub only: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    pub not: Option<Vec<ApplicationMatcher>>,
+    pub mode: String,
+}
+
+impl ApplicationMode {
+    pub fn matches(&self, application: &str) -> bool {
+        matches_only_or_not(&self.only, &self.not, application)
+    }
+}
+
+// mode_memory: What mode and mark_set are remembered for
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModeMemory {
+    Application,
+    Window,
+}
+
+fn matches_only_or_not(
+    only: &Option<Vec<ApplicationMatcher>>,
+    not: &Option<Vec<ApplicationMatcher>>,
+    application: &str,
+) -> bool {
+    if let Some(only) = only {
+        return only.iter().any(|m| m.matches(application));
+    }
+    if let Some(not) = not {
+        return not.iter().all(|m| !m.matches(application));
+    }
+    false
+}
+
 #[derive(Clone, Debug)]
 pub enum ApplicationMatcher {
     // class.name
     Literal(String),
     // name
     Name(String),
-    // /regex/
+    // /regex/ or /regex/flags, where flags are any of i, x and s
     Regex(Regex),
+    // glob:pattern, where * matches any string and ? matches any character
+    Glob(String, Regex),
+    // substring:string
+    Substring(String),
+}
+
+impl PartialEq for ApplicationMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ApplicationMatcher::Literal(a), ApplicationMatcher::Literal(b)) => a == b,
+            (ApplicationMatcher::Name(a), ApplicationMatcher::Name(b)) => a == b,
+            (ApplicationMatcher::Regex(a), ApplicationMatcher::Regex(b)) => a.as_str() == b.as_str(),
+            (ApplicationMatcher::Glob(a, _), ApplicationMatcher::Glob(b, _)) => a == b,
+            (ApplicationMatcher::Substring(a), ApplicationMatcher::Substring(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 impl ApplicationMatcher {
@@ -35,7 +92,19 @@ impl ApplicationMatcher {
                     s == app
                 }
             }
-            ApplicationMatcher::Regex(r) => r.is_match(app),
+            ApplicationMatcher::Regex(r) | ApplicationMatcher::Glob(_, r) => r.is_match(app),
+            ApplicationMatcher::Substring(s) => app.contains(s.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for ApplicationMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplicationMatcher::Literal(s) | ApplicationMatcher::Name(s) => write!(f, "{s}"),
+            ApplicationMatcher::Regex(r) => write!(f, "/{}/", r.as_str().replace('/', "\\/")),
+            ApplicationMatcher::Glob(s, _) => write!(f, "glob:{s}"),
+            ApplicationMatcher::Substring(s) => write!(f, "substring:{s}"),
         }
     }
 }
@@ -44,8 +113,23 @@ impl FromStr for ApplicationMatcher {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(pattern) = s.strip_prefix("glob:") {
+            return Ok(ApplicationMatcher::Glob(pattern.to_owned(), glob_to_regex(pattern)?));
+        }
+        if let Some(substring) = s.strip_prefix("substring:") {
+            return Ok(ApplicationMatcher::Substring(substring.to_owned()));
+        }
         match s.as_bytes() {
-            [b'/', ..] => Ok(ApplicationMatcher::Regex(Regex::new(&slash_unescape(s)?)?)),
+            [b'/', ..] => {
+                let (regex, flags) = slash_unescape(s)?;
+                // Inline flags keep them in Regex::as_str(), which PartialEq and Display rely on
+                let regex = if flags.is_empty() {
+                    regex
+                } else {
+                    format!("(?{flags}){regex}")
+                };
+                Ok(ApplicationMatcher::Regex(Regex::new(&regex)?))
+            }
             _ => {
                 if s.find('.').is_some() {
                     Ok(ApplicationMatcher::Literal(s.to_owned()))
@@ -57,13 +141,32 @@ impl FromStr for ApplicationMatcher {
     }
 }
 
-fn slash_unescape(s: &str) -> anyhow::Result<String> {
+fn glob_to_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+// Split /regex/flags into the unescaped regex and the flags
+fn slash_unescape(s: &str) -> anyhow::Result<(String, String)> {
     let mut result = String::with_capacity(s.len());
+    let mut flags = String::new();
     let mut escaping = false;
     let mut finished = false;
     for c in s.chars().skip(1) {
         if finished {
-            return Err(anyhow!("Unexpected trailing string after closing / in application name regex"));
+            if !matches!(c, 'i' | 'x' | 's') {
+                return Err(anyhow!("Unexpected trailing string after closing / in application name regex"));
+            }
+            flags.push(c);
+            continue;
         }
         if escaping {
             escaping = false;
@@ -82,10 +185,10 @@ fn slash_unescape(s: &str) -> anyhow::Result<String> {
     if !finished {
         return Err(anyhow!("Missing closing / in application name regex"));
     }
-    Ok(result)
+    Ok((result, flags))
 }
 
-fn deserialize_matchers<'de, D>(deserializer: D) -> Result<Option<Vec<ApplicationMatcher>>, D::Error>
+pub fn deserialize_matchers<'de, D>(deserializer: D) -> Result<Option<Vec<ApplicationMatcher>>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -149,7 +252,7 @@ mod tests {
     fn test_unescape_slash_correct_regex() {
         let given = r"/^Mine\d\/craft\\/";
         let got = slash_unescape(given).unwrap();
-        assert_eq!(r"^Mine\d/craft\\", got);
+        assert_eq!((r"^Mine\d/craft\\".to_string(), String::new()), got);
     }
 
     #[test]
@@ -161,8 +264,34 @@ mod tests {
 
     #[test]
     fn test_unescape_slash_excessive_string_after_closing() {
-        let given = r"/^Minecraft/i";
+        let given = r"/^Minecraft/g";
         let got = slash_unescape(given).unwrap_err();
         assert_eq!("Unexpected trailing string after closing / in application name regex", got.to_string());
     }
+
+    #[test]
+    fn test_regex_flags_application_name_matcher() {
+        let matcher = ApplicationMatcher::from_str(r"/^firefox$/i").unwrap();
+        assert!(matcher.matches("Firefox"), "Failed to match Firefox case-insensitively");
+        assert_eq!("/(?i)^firefox$/", matcher.to_string());
+        assert_ne!(ApplicationMatcher::from_str(r"/^firefox$/").unwrap(), matcher);
+    }
+
+    #[test]
+    fn test_glob_application_name_matcher() {
+        let matcher = ApplicationMatcher::from_str("glob:*term*").unwrap();
+        assert!(matcher.matches("gnome-terminal"), "Failed to match gnome-terminal using glob");
+        assert!(!matcher.matches("Firefox"));
+        let matcher = ApplicationMatcher::from_str("glob:?term").unwrap();
+        assert!(matcher.matches("xterm"), "Failed to match xterm using glob");
+        assert!(!matcher.matches("uxterm"), "? should match exactly one character");
+        assert!(!matcher.matches("xterm.app"), "Glob should match the whole string");
+    }
+
+    #[test]
+    fn test_substring_application_name_matcher() {
+        let matcher = ApplicationMatcher::from_str("substring:Docs").unwrap();
+        assert!(matcher.matches("Untitled - Google Docs - Firefox"), "Failed to match a substring");
+        assert!(!matcher.matches("Google Sheets"));
+    }
 }
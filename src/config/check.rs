@@ -0,0 +1,312 @@
+use crate::config::condition::Condition;
+use crate::config::key_press::{KeyPress, Modifier};
+use crate::config::keymap::Keymap;
+use crate::config::keymap_action::KeymapAction;
+use crate::config::modmap_action::ModmapAction;
+use crate::config::Config;
+use std::collections::HashSet;
+
+// Find config entries that parse fine but can't work as written. Used by `xremap check`.
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut warnings = vec![];
+    check_shadowed_keymaps(config, &mut warnings);
+    check_unset_modes(config, &mut warnings);
+    check_virtual_modifier_remaps(config, &mut warnings);
+    check_duplicate_matchers(config, &mut warnings);
+    warnings
+}
+
+// An entry is unreachable if an earlier keymap always wins the first-match lookup in find_keymap.
+fn check_shadowed_keymaps(config: &Config, warnings: &mut Vec<String>) {
+    for (index, keymap) in config.keymap.iter().enumerate() {
+        for (key_press, actions) in sorted_remap(keymap) {
+            let shadowing = config.keymap[..index].iter().enumerate().find(|(_, earlier)| {
+                earlier.remap.iter().any(|(earlier_press, earlier_actions)| {
+                    same_key_press(earlier_press, key_press)
+                        && !(is_remap(earlier_actions) && is_remap(actions))
+                        && shadows(earlier, keymap)
+                })
+            });
+            if let Some((earlier_index, earlier)) = shadowing {
+                warnings.push(format!(
+                    "{}: {} is unreachable because {} matches it first",
+                    keymap_label("keymap", index, &keymap.name),
+                    key_press,
+                    keymap_label("keymap", earlier_index, &earlier.name),
+                ));
+            }
+        }
+    }
+}
+
+fn check_unset_modes(config: &Config, warnings: &mut Vec<String>) {
+    let set_modes = set_modes(config);
+    for (index, keymap) in config.keymap.iter().enumerate() {
+        let condition = match &keymap.condition {
+            Some(condition) => condition,
+            None => continue,
+        };
+        visit_leaves(condition, false, &mut |condition, _| {
+            if let Condition::Mode(modes) = condition {
+                for mode in modes {
+                    if !set_modes.contains(mode.as_str()) {
+                        warnings.push(format!(
+                            "{}: mode '{}' is never set by set_mode or application_modes and isn't the default_mode",
+                            keymap_label("keymap", index, &keymap.name),
+                            mode,
+                        ));
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Virtual modifiers are consumed before keymap lookup, so they never trigger a keymap on their own.
+fn check_virtual_modifier_remaps(config: &Config, warnings: &mut Vec<String>) {
+    for (index, keymap) in config.keymap.iter().enumerate() {
+        for (key_press, _) in sorted_remap(keymap) {
+            if config.virtual_modifiers.contains(&key_press.key) {
+                warnings.push(format!(
+                    "{}: {} never triggers because {:?} is a virtual_modifier",
+                    keymap_label("keymap", index, &keymap.name),
+                    key_press,
+                    key_press.key,
+                ));
+            }
+        }
+    }
+}
+
+fn check_duplicate_matchers(config: &Config, warnings: &mut Vec<String>) {
+    for (index, modmap) in config.modmap.iter().enumerate() {
+        let label = keymap_label("modmap", index, &modmap.name);
+        check_duplicate_matcher(&label, &modmap.condition, warnings);
+    }
+    for (index, keymap) in config.keymap.iter().enumerate() {
+        let label = keymap_label("keymap", index, &keymap.name);
+        check_duplicate_matcher(&label, &keymap.condition, warnings);
+    }
+}
+
+fn check_duplicate_matcher(label: &str, condition: &Option<Condition>, warnings: &mut Vec<String>) {
+    let condition = match condition {
+        Some(condition) => condition,
+        None => return,
+    };
+    visit_leaves(condition, false, &mut |condition, negated| {
+        let (field, matchers) = match condition {
+            Condition::Application(matchers) => ("application", matchers),
+            Condition::Window(matchers) => ("window", matchers),
+            _ => return,
+        };
+        let kind = if negated { "not" } else { "only" };
+        for (i, m) in matchers.iter().enumerate() {
+            if matchers[..i].contains(m) {
+                warnings.push(format!("{label}: {field}.{kind} has a duplicate matcher '{m}'"));
+            }
+        }
+    });
+}
+
+// Call `visit` with each leaf of a condition and whether it's negated
+fn visit_leaves<'a>(condition: &'a Condition, negated: bool, visit: &mut impl FnMut(&'a Condition, bool)) {
+    match condition {
+        Condition::All(conditions) | Condition::Any(conditions) => {
+            for condition in conditions {
+                visit_leaves(condition, negated, visit);
+            }
+        }
+        Condition::Not(condition) => visit_leaves(condition, !negated, visit),
+        leaf => visit(leaf, negated),
+    }
+}
+
+// Modes that can be the current mode: the default_mode, every mode of set_mode, and application_modes
+pub fn set_modes(config: &Config) -> HashSet<&str> {
+    let mut set_modes: HashSet<&str> = HashSet::new();
+    set_modes.insert(&config.default_mode);
+    for application_mode in &config.application_modes {
+        set_modes.insert(&application_mode.mode);
+    }
+    for keymap in &config.keymap {
+        for actions in keymap.remap.values() {
+            collect_set_modes(actions, &mut set_modes);
+        }
+    }
+    for modmap in &config.modmap {
+        for action in modmap.remap.values() {
+            if let ModmapAction::PressReleaseKey(press_release) = action {
+                collect_set_modes(&press_release.press, &mut set_modes);
+                collect_set_modes(&press_release.release, &mut set_modes);
+            }
+        }
+    }
+    set_modes
+}
+
+fn collect_set_modes<'a>(actions: &'a [KeymapAction], modes: &mut HashSet<&'a str>) {
+    for action in actions {
+        match action {
+            KeymapAction::SetMode(mode) => {
+                modes.insert(mode);
+            }
+            KeymapAction::Remap(remap) => {
+                for actions in remap.remap.values() {
+                    collect_set_modes(actions, modes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Whether `earlier` matches every situation `later` matches, ignoring modifiers
+fn shadows(earlier: &Keymap, later: &Keymap) -> bool {
+    covers(&earlier.condition, &later.condition)
+        // A non-exact later entry may still match key presses with extra modifiers
+        && (!earlier.exact_match || later.exact_match)
+}
+
+// `earlier` matches whenever `later` does if every condition `earlier` requires is required by `later` too
+fn covers(earlier: &Option<Condition>, later: &Option<Condition>) -> bool {
+    let later = conjuncts(later);
+    conjuncts(earlier).iter().all(|condition| later.contains(condition))
+}
+
+fn conjuncts(condition: &Option<Condition>) -> Vec<&Condition> {
+    match condition {
+        None => vec![],
+        Some(Condition::All(conditions)) => conditions.iter().collect(),
+        Some(condition) => vec![condition],
+    }
+}
+
+fn same_key_press(a: &KeyPress, b: &KeyPress) -> bool {
+    a.key == b.key && same_modifiers(&a.modifiers, &b.modifiers)
+}
+
+fn same_modifiers(a: &[Modifier], b: &[Modifier]) -> bool {
+    a.iter().all(|m| b.contains(m)) && b.iter().all(|m| a.contains(m))
+}
+
+fn is_remap(actions: &[KeymapAction]) -> bool {
+    actions.iter().all(|action| matches!(action, KeymapAction::Remap(..)))
+}
+
+// HashMap order is random, so sort entries to report them in a stable order
+fn sorted_remap(keymap: &Keymap) -> Vec<(&KeyPress, &Vec<KeymapAction>)> {
+    let mut remap: Vec<_> = keymap.remap.iter().collect();
+    remap.sort_by_key(|(key_press, _)| key_press.to_string());
+    remap
+}
+
+fn keymap_label(kind: &str, index: usize, name: &str) -> String {
+    if name.is_empty() {
+        format!("{kind}[{index}]")
+    } else {
+        format!("{kind}[{index}] '{name}'")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::keymap::build_keymap_table;
+    use indoc::indoc;
+
+    fn check_yaml(yaml: &str) -> Vec<String> {
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.keymap_table = build_keymap_table(&config.keymap);
+        check_config(&config)
+    }
+
+    #[test]
+    fn test_shadowed_keymap() {
+        let warnings = check_yaml(indoc! {"
+        keymap:
+          - name: Global
+            remap:
+              C-b: left
+          - application:
+              only: Firefox
+            remap:
+              Ctrl-b: right
+              C-f: right
+        "});
+        assert_eq!(vec!["keymap[1]: C-KEY_B is unreachable because keymap[0] 'Global' matches it first"], warnings);
+    }
+
+    #[test]
+    fn test_not_shadowed_by_narrower_keymap() {
+        let warnings = check_yaml(indoc! {"
+        keymap:
+          - application:
+              only: Firefox
+            remap:
+              C-b: left
+          - exact_match: true
+            remap:
+              C-b: left
+          - remap:
+              C-b:
+                remap:
+                  x: y
+          - remap:
+              C-b:
+                remap:
+                  z: w
+        "});
+        assert_eq!(Vec::<String>::new(), warnings);
+    }
+
+    #[test]
+    fn test_unset_mode() {
+        let warnings = check_yaml(indoc! {"
+        default_mode: insert
+        keymap:
+          - mode: insert
+            remap:
+              Esc: { set_mode: normal }
+          - mode: [normal, visual]
+            remap:
+              i: { set_mode: insert }
+        "});
+        assert_eq!(
+            vec!["keymap[1]: mode 'visual' is never set by set_mode or application_modes and isn't the default_mode"],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_virtual_modifier_remap() {
+        let warnings = check_yaml(indoc! {"
+        virtual_modifiers:
+          - CapsLock
+        keymap:
+          - remap:
+              CapsLock: Esc
+              CapsLock-i: Up
+        "});
+        assert_eq!(vec!["keymap[0]: KEY_CAPSLOCK never triggers because KEY_CAPSLOCK is a virtual_modifier"], warnings);
+    }
+
+    #[test]
+    fn test_duplicate_matchers() {
+        let warnings = check_yaml(indoc! {"
+        modmap:
+          - name: Terminals
+            application:
+              only: [Alacritty, /kitty/, Alacritty, /kitty/]
+            remap:
+              CapsLock: Esc
+        "});
+        assert_eq!(
+            vec![
+                "modmap[0] 'Terminals': application.only has a duplicate matcher 'Alacritty'",
+                "modmap[0] 'Terminals': application.only has a duplicate matcher '/kitty/'",
+            ],
+            warnings
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::config::application::{deserialize_matchers, deserialize_string_or_vec, ApplicationMatcher, OnlyOrNot};
+use crate::config::device::{deserialize_device_matchers, Device, DeviceMatcher};
+use crate::config::key::parse_key;
+use crate::config::window_properties::WindowPropertiesMatcher;
+use evdev::Key;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+
+// keymap/modmap `when`: A boolean expression over the context of a key press.
+// The legacy application/window/window_properties/device/mode fields are folded into it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    // Each of the following matches if any of the matchers matches
+    Application(Vec<ApplicationMatcher>),
+    Window(Vec<ApplicationMatcher>),
+    Executable(Vec<ApplicationMatcher>),
+    Workspace(Vec<ApplicationMatcher>),
+    Output(Vec<ApplicationMatcher>),
+    ResourceName(Vec<ApplicationMatcher>),
+    ResourceClass(Vec<ApplicationMatcher>),
+    Device(Vec<DeviceMatcher>),
+    Mode(Vec<String>),
+    Floating(bool),
+    Fullscreen(bool),
+    Xwayland(bool),
+    // All of the keys are held
+    Pressed(Vec<Key>),
+    // All of the environment variables have the values
+    Env(HashMap<String, String>),
+}
+
+// Used only for deserialization. Multiple fields in a map mean all of them.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConditionFields {
+    all: Option<Vec<Condition>>,
+    any: Option<Vec<Condition>>,
+    not: Option<Box<Condition>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    application: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    window: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    executable: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    workspace: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    output: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    resource_name: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_matchers")]
+    resource_class: Option<Vec<ApplicationMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_device_matchers")]
+    device: Option<Vec<DeviceMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
+    mode: Option<Vec<String>>,
+    floating: Option<bool>,
+    fullscreen: Option<bool>,
+    xwayland: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_pressed")]
+    pressed: Option<Vec<Key>>,
+    env: Option<HashMap<String, String>>,
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let fields = ConditionFields::deserialize(deserializer)?;
+        let conditions: Vec<Condition> = [
+            fields.all.map(Condition::All),
+            fields.any.map(Condition::Any),
+            fields.not.map(Condition::Not),
+            fields.application.map(Condition::Application),
+            fields.window.map(Condition::Window),
+            fields.executable.map(Condition::Executable),
+            fields.workspace.map(Condition::Workspace),
+            fields.output.map(Condition::Output),
+            fields.resource_name.map(Condition::ResourceName),
+            fields.resource_class.map(Condition::ResourceClass),
+            fields.device.map(Condition::Device),
+            fields.mode.map(Condition::Mode),
+            fields.floating.map(Condition::Floating),
+            fields.fullscreen.map(Condition::Fullscreen),
+            fields.xwayland.map(Condition::Xwayland),
+            fields.pressed.map(Condition::Pressed),
+            fields.env.map(Condition::Env),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        match conditions.len() {
+            0 => Err(de::Error::custom("empty condition")),
+            1 => Ok(conditions.into_iter().next().expect("a condition")),
+            _ => Ok(Condition::All(conditions)),
+        }
+    }
+}
+
+fn deserialize_pressed<'de, D>(deserializer: D) -> Result<Option<Vec<Key>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = deserialize_string_or_vec(deserializer)?.unwrap_or_default();
+    let keys: Result<Vec<Key>, _> = keys.iter().map(|key| parse_key(key)).collect();
+    keys.map(Some).map_err(de::Error::custom)
+}
+
+// The legacy conditions of a keymap/modmap
+pub struct LegacyConditions<'a> {
+    pub application: &'a Option<OnlyOrNot>,
+    pub window: &'a Option<OnlyOrNot>,
+    pub window_properties: &'a Option<WindowPropertiesMatcher>,
+    pub device: &'a Option<Device>,
+    pub mode: &'a Option<Vec<String>>,
+}
+
+// Fold the legacy conditions and `when` into a single Condition. None if it always matches.
+pub fn fold_conditions(legacy: LegacyConditions, when: Option<Condition>) -> Option<Condition> {
+    let mut conditions = vec![];
+    if let Some(window) = legacy.window {
+        conditions.push(only_or_not(window, Condition::Window));
+    }
+    if let Some(application) = legacy.application {
+        conditions.push(only_or_not(application, Condition::Application));
+    }
+    if let Some(properties) = legacy.window_properties {
+        let matchers = [
+            (&properties.executable, Condition::Executable as fn(_) -> _),
+            (&properties.workspace, Condition::Workspace),
+            (&properties.output, Condition::Output),
+            (&properties.resource_name, Condition::ResourceName),
+            (&properties.resource_class, Condition::ResourceClass),
+        ];
+        for (matcher, condition) in matchers {
+            if let Some(matcher) = matcher {
+                conditions.push(property_only_or_not(matcher, condition));
+            }
+        }
+        conditions.extend(properties.floating.map(Condition::Floating));
+        conditions.extend(properties.fullscreen.map(Condition::Fullscreen));
+        conditions.extend(properties.xwayland.map(Condition::Xwayland));
+    }
+    if let Some(device) = legacy.device {
+        conditions.push(match (&device.only, &device.not) {
+            (Some(only), _) => Condition::Device(only.clone()),
+            (None, Some(not)) => Condition::Not(Box::new(Condition::Device(not.clone()))),
+            (None, None) => Condition::Any(vec![]),
+        });
+    }
+    if let Some(modes) = legacy.mode {
+        conditions.push(Condition::Mode(modes.clone()));
+    }
+    conditions.extend(when);
+    match conditions.len() {
+        0 => None,
+        1 => conditions.pop(),
+        _ => Some(Condition::All(conditions)),
+    }
+}
+
+// `only` wins over `not` like it did before `when`, and an empty OnlyOrNot never matches
+fn only_or_not(matcher: &OnlyOrNot, condition: fn(Vec<ApplicationMatcher>) -> Condition) -> Condition {
+    match (&matcher.only, &matcher.not) {
+        (Some(only), _) => condition(only.clone()),
+        (None, Some(not)) => Condition::Not(Box::new(condition(not.clone()))),
+        (None, None) => Condition::Any(vec![]),
+    }
+}
+
+// Unlike the application and the window, a property the client doesn't know matches neither `only` nor `not`
+fn property_only_or_not(matcher: &OnlyOrNot, condition: fn(Vec<ApplicationMatcher>) -> Condition) -> Condition {
+    match only_or_not(matcher, condition) {
+        Condition::Not(not) => {
+            // An empty substring matches any value the client knows
+            let known = condition(vec![ApplicationMatcher::Substring(String::new())]);
+            Condition::All(vec![known, Condition::Not(not)])
+        }
+        condition => condition,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_deserialize_condition() {
+        let condition: Condition = serde_yaml::from_str(
+            r#"
+            all:
+              - application: Firefox
+              - not: { window: /Google Docs/ }
+              - { mode: insert, pressed: [Shift_L] }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Condition::All(vec![
+                Condition::Application(vec![ApplicationMatcher::from_str("Firefox").unwrap()]),
+                Condition::Not(Box::new(Condition::Window(vec![
+                    ApplicationMatcher::from_str("/Google Docs/").unwrap()
+                ]))),
+                Condition::All(vec![
+                    Condition::Mode(vec!["insert".to_string()]),
+                    Condition::Pressed(vec![Key::KEY_LEFTSHIFT]),
+                ]),
+            ]),
+            condition
+        );
+        assert!(serde_yaml::from_str::<Condition>("{}").is_err());
+        assert!(serde_yaml::from_str::<Condition>("{ applications: Firefox }").is_err());
+    }
+}
@@ -1,12 +1,215 @@
-use crate::config::application::deserialize_string_or_vec;
-use serde::Deserialize;
+use crate::config::application::{deserialize_string_or_vec, ApplicationMatcher};
+use anyhow::anyhow;
+use evdev::BusType;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
 
 // TODO: Use trait to allow only either `only` or `not`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Device {
-    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
-    pub only: Option<Vec<String>>,
-    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
-    pub not: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_device_matchers")]
+    pub only: Option<Vec<DeviceMatcher>>,
+    #[serde(default, deserialize_with = "deserialize_device_matchers")]
+    pub not: Option<Vec<DeviceMatcher>>,
+}
+
+// devices: Device selection like --device, --ignore and --mouse, which override it
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Devices {
+    #[serde(default, deserialize_with = "deserialize_device_matcher_list")]
+    pub include: Vec<DeviceMatcher>,
+    #[serde(default, deserialize_with = "deserialize_device_matcher_list")]
+    pub ignore: Vec<DeviceMatcher>,
+    #[serde(default)]
+    pub mouse: bool,
+    // The first entry matching a selected device applies to it
+    #[serde(default)]
+    pub options: Vec<DeviceOptions>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceOptions {
+    #[serde(deserialize_with = "deserialize_device_matcher_list")]
+    pub device: Vec<DeviceMatcher>,
+    // false to remap the device without taking its events from other applications
+    #[serde(default = "default_grab")]
+    pub grab: bool,
+    // Emit events remapped from the device through another output device of this name
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+impl Default for DeviceOptions {
+    fn default() -> Self {
+        DeviceOptions {
+            device: vec![],
+            grab: default_grab(),
+            output: None,
+        }
+    }
+}
+
+fn default_grab() -> bool {
+    true
+}
+
+// Used by device filters of keymap/modmap and --device/--ignore
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceMatcher {
+    // The full path, a /dev/input/by-id or by-path link, eventXX, the name, or a part of the name
+    Name(String),
+    // /regex/flags, glob:pattern or substring:string matching the name, the full path or a link
+    Pattern(ApplicationMatcher),
+    // id:vendor or id:vendor:product in hex, e.g. id:046d:c52b
+    Id(u16, Option<u16>),
+    // bus:usb, bus:bluetooth, bus:i8042, etc.
+    Bus(BusType),
+    // phys:string or phys:pattern
+    Phys(ApplicationMatcher),
+    // uniq:string or uniq:pattern, e.g. the MAC address of a Bluetooth device
+    Uniq(ApplicationMatcher),
+    // has:keyboard, has:mouse, has:touchpad or has:touchscreen
+    Capability(Capability),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Keyboard,
+    Mouse,
+    Touchpad,
+    Touchscreen,
+}
+
+impl fmt::Display for DeviceMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceMatcher::Name(name) => write!(f, "{name}"),
+            DeviceMatcher::Pattern(matcher) => write!(f, "{matcher}"),
+            DeviceMatcher::Id(vendor, None) => write!(f, "id:{vendor:04x}"),
+            DeviceMatcher::Id(vendor, Some(product)) => write!(f, "id:{vendor:04x}:{product:04x}"),
+            DeviceMatcher::Bus(bus) => {
+                write!(f, "bus:{}", format!("{bus:?}").trim_start_matches("BUS_").to_lowercase())
+            }
+            DeviceMatcher::Phys(matcher) => write!(f, "phys:{matcher}"),
+            DeviceMatcher::Uniq(matcher) => write!(f, "uniq:{matcher}"),
+            DeviceMatcher::Capability(capability) => write!(f, "has:{}", format!("{capability:?}").to_lowercase()),
+        }
+    }
+}
+
+impl FromStr for DeviceMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(id) = s.strip_prefix("id:") {
+            let parse = |hex: &str| u16::from_str_radix(hex, 16).map_err(|_| anyhow!("Invalid device id '{s}'"));
+            return match id.split_once(':') {
+                Some((vendor, product)) => Ok(DeviceMatcher::Id(parse(vendor)?, Some(parse(product)?))),
+                None => Ok(DeviceMatcher::Id(parse(id)?, None)),
+            };
+        }
+        if let Some(bus) = s.strip_prefix("bus:") {
+            let name = bus.to_uppercase();
+            let name = if name.starts_with("BUS_") {
+                name
+            } else {
+                format!("BUS_{name}")
+            };
+            return BusType::from_str(&name)
+                .map(DeviceMatcher::Bus)
+                .map_err(|_| anyhow!("Unknown device bus '{bus}'"));
+        }
+        if let Some(phys) = s.strip_prefix("phys:") {
+            return Ok(DeviceMatcher::Phys(value_matcher(phys)?));
+        }
+        if let Some(uniq) = s.strip_prefix("uniq:") {
+            return Ok(DeviceMatcher::Uniq(value_matcher(uniq)?));
+        }
+        if let Some(capability) = s.strip_prefix("has:") {
+            let capability = match capability.to_lowercase().as_str() {
+                "keyboard" => Capability::Keyboard,
+                "mouse" => Capability::Mouse,
+                "touchpad" => Capability::Touchpad,
+                "touchscreen" => Capability::Touchscreen,
+                _ => return Err(anyhow!("Unknown device capability '{capability}'")),
+            };
+            return Ok(DeviceMatcher::Capability(capability));
+        }
+        // Paths under /dev/ have been device paths before regexes were supported
+        if is_pattern(s) && !s.starts_with("/dev/") {
+            Ok(DeviceMatcher::Pattern(ApplicationMatcher::from_str(s)?))
+        } else {
+            Ok(DeviceMatcher::Name(s.to_owned()))
+        }
+    }
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.starts_with("glob:") || s.starts_with("substring:") || s.starts_with('/')
+}
+
+// A pattern, or a string matching exactly
+fn value_matcher(s: &str) -> anyhow::Result<ApplicationMatcher> {
+    if is_pattern(s) {
+        ApplicationMatcher::from_str(s)
+    } else {
+        Ok(ApplicationMatcher::Literal(s.to_owned()))
+    }
+}
+
+pub fn deserialize_device_matchers<'de, D>(deserializer: D) -> Result<Option<Vec<DeviceMatcher>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize_string_or_vec(deserializer)? {
+        None => Ok(None),
+        Some(strings) => strings
+            .iter()
+            .map(|s| DeviceMatcher::from_str(s).map_err(serde::de::Error::custom))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+    }
+}
+
+fn deserialize_device_matcher_list<'de, D>(deserializer: D) -> Result<Vec<DeviceMatcher>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(deserialize_device_matchers(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_device_matcher() {
+        let parse = |s: &str| DeviceMatcher::from_str(s).unwrap();
+        assert_eq!(DeviceMatcher::Id(0x046d, Some(0xc52b)), parse("id:046d:c52b"));
+        assert_eq!(DeviceMatcher::Id(0x046d, None), parse("id:046D"));
+        assert_eq!(DeviceMatcher::Bus(BusType::BUS_BLUETOOTH), parse("bus:Bluetooth"));
+        assert_eq!(DeviceMatcher::Bus(BusType::BUS_I8042), parse("bus:BUS_I8042"));
+        assert_eq!(DeviceMatcher::Capability(Capability::Touchpad), parse("has:touchpad"));
+        assert_eq!(
+            DeviceMatcher::Uniq(ApplicationMatcher::Literal("aa:bb:cc:dd:ee:ff".to_string())),
+            parse("uniq:aa:bb:cc:dd:ee:ff")
+        );
+        assert_eq!(DeviceMatcher::Name("/dev/input/by-id/usb-kbd".to_string()), parse("/dev/input/by-id/usb-kbd"));
+        for s in [
+            "id:046d:c52b",
+            "bus:bluetooth",
+            "phys:glob:usb-*",
+            "uniq:/^aa:/",
+            "has:touchscreen",
+        ] {
+            assert_eq!(s, parse(s).to_string());
+        }
+        assert!(DeviceMatcher::from_str("id:logitech").is_err());
+        assert!(DeviceMatcher::from_str("bus:serial").is_err());
+        assert!(DeviceMatcher::from_str("has:wheel").is_err());
+    }
 }
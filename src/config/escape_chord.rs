@@ -0,0 +1,47 @@
+use crate::config::deserialize_keys;
+use evdev::Key;
+use serde::Deserialize;
+use serde_with::{serde_as, DurationMilliSeconds};
+use std::time::Duration;
+
+// Keys to regain control of the keyboard when a config makes it unusable
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EscapeChord {
+    #[serde(deserialize_with = "deserialize_keys", default = "default_keys")]
+    pub keys: Vec<Key>,
+    #[serde_as(as = "DurationMilliSeconds")]
+    #[serde(default = "default_hold", rename = "hold_millis")]
+    pub hold: Duration,
+    #[serde(default)]
+    pub action: EscapeChordAction,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EscapeChordAction {
+    // Ungrab the devices and exit
+    #[default]
+    Exit,
+    // Ungrab the devices until the chord is held again
+    Pause,
+}
+
+impl Default for EscapeChord {
+    fn default() -> Self {
+        EscapeChord {
+            keys: default_keys(),
+            hold: default_hold(),
+            action: EscapeChordAction::default(),
+        }
+    }
+}
+
+fn default_keys() -> Vec<Key> {
+    vec![Key::KEY_LEFTSHIFT, Key::KEY_RIGHTSHIFT, Key::KEY_ESC]
+}
+
+fn default_hold() -> Duration {
+    Duration::from_millis(3000)
+}
@@ -130,3 +130,58 @@ pub fn parse_key(input: &str) -> Result<Key, Box<dyn Error>> {
 
     return Err(format!("unknown key '{}'", input).into());
 }
+
+// Custom aliases of parse_key for disguised relative events, in the order of their scancodes
+const DISGUISED_KEY_NAMES: [&str; 26] = [
+    "XRIGHTCURSOR",
+    "XLEFTCURSOR",
+    "XDOWNCURSOR",
+    "XUPCURSOR",
+    "XREL_Z_AXIS_1",
+    "XREL_Z_AXIS_2",
+    "XREL_RX_AXIS_1",
+    "XREL_RX_AXIS_2",
+    "XREL_RY_AXIS_1",
+    "XREL_RY_AXIS_2",
+    "XREL_RZ_AXIS_1",
+    "XREL_RZ_AXIS_2",
+    "XRIGHTSCROLL",
+    "XLEFTSCROLL",
+    "XREL_DIAL_1",
+    "XREL_DIAL_2",
+    "XUPSCROLL",
+    "XDOWNSCROLL",
+    "XREL_MISC_1",
+    "XREL_MISC_2",
+    "XREL_RESERVED_1",
+    "XREL_RESERVED_2",
+    "XHIRES_UPSCROLL",
+    "XHIRES_DOWNSCROLL",
+    "XHIRES_RIGHTSCROLL",
+    "XHIRES_LEFTSCROLL",
+];
+
+// The name to write in config files for a key, the inverse of parse_key
+pub fn key_name(key: Key) -> String {
+    if let Some(offset) = key.code().checked_sub(DISGUISED_EVENT_OFFSETTER) {
+        if let Some(name) = DISGUISED_KEY_NAMES.get(offset as usize) {
+            return name.to_string();
+        }
+    }
+    format!("{:?}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_name() {
+        assert_eq!("KEY_102ND", key_name(Key::KEY_102ND));
+        assert_eq!("XHIRES_UPSCROLL", key_name(Key(DISGUISED_EVENT_OFFSETTER + 22)));
+        for offset in 0..DISGUISED_KEY_NAMES.len() as u16 {
+            let key = Key(DISGUISED_EVENT_OFFSETTER + offset);
+            assert_eq!(key, parse_key(&key_name(key)).unwrap());
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::config::key::parse_key;
 use evdev::Key;
 use serde::{Deserialize, Deserializer};
 use std::error::{self, Error};
+use std::fmt;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct KeyPress {
@@ -20,6 +21,21 @@ pub enum Modifier {
     Key(Key),
 }
 
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in &self.modifiers {
+            match modifier {
+                Modifier::Shift => write!(f, "Shift-")?,
+                Modifier::Control => write!(f, "C-")?,
+                Modifier::Alt => write!(f, "M-")?,
+                Modifier::Windows => write!(f, "Super-")?,
+                Modifier::Key(key) => write!(f, "{key:?}-")?,
+            }
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
 impl<'de> Deserialize<'de> for KeyPress {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -30,7 +46,7 @@ impl<'de> Deserialize<'de> for KeyPress {
     }
 }
 
-fn parse_key_press(input: &str) -> Result<KeyPress, Box<dyn error::Error>> {
+pub fn parse_key_press(input: &str) -> Result<KeyPress, Box<dyn error::Error>> {
     let keys: Vec<&str> = input.split('-').collect();
     if let Some((key, modifier_keys)) = keys.split_last() {
         let mut modifiers = vec![];
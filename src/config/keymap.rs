@@ -6,24 +6,57 @@ use evdev::Key;
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
+use super::condition::{fold_conditions, Condition, LegacyConditions};
 use super::device::Device;
 use super::key_press::Modifier;
+use super::window_properties::WindowPropertiesMatcher;
 
 // Config interface
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[serde(from = "KeymapFields")]
 pub struct Keymap {
-    #[serde(default = "String::new")]
     pub name: String,
-    #[serde(deserialize_with = "deserialize_remap")]
     pub remap: HashMap<KeyPress, Vec<KeymapAction>>,
-    pub application: Option<OnlyOrNot>,
-    pub window: Option<OnlyOrNot>,
-    pub device: Option<Device>,
+    // `when` and the legacy conditions. None if it always matches.
+    pub condition: Option<Condition>,
+    pub exact_match: bool,
+}
+
+// Used only for deserialization
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeymapFields {
+    #[serde(default = "String::new")]
+    name: String,
+    #[serde(deserialize_with = "deserialize_remap")]
+    remap: HashMap<KeyPress, Vec<KeymapAction>>,
+    application: Option<OnlyOrNot>,
+    window: Option<OnlyOrNot>,
+    window_properties: Option<WindowPropertiesMatcher>,
+    device: Option<Device>,
     #[serde(default, deserialize_with = "deserialize_string_or_vec")]
-    pub mode: Option<Vec<String>>,
+    mode: Option<Vec<String>>,
+    when: Option<Condition>,
     #[serde(default)]
-    pub exact_match: bool,
+    exact_match: bool,
+}
+
+impl From<KeymapFields> for Keymap {
+    fn from(fields: KeymapFields) -> Self {
+        let legacy = LegacyConditions {
+            application: &fields.application,
+            window: &fields.window,
+            window_properties: &fields.window_properties,
+            device: &fields.device,
+            mode: &fields.mode,
+        };
+        Keymap {
+            condition: fold_conditions(legacy, fields.when),
+            name: fields.name,
+            remap: fields.remap,
+            exact_match: fields.exact_match,
+        }
+    }
 }
 
 fn deserialize_remap<'de, D>(deserializer: D) -> Result<HashMap<KeyPress, Vec<KeymapAction>>, D::Error>
@@ -40,12 +73,11 @@ where
 // Internals for efficient keymap lookup
 #[derive(Clone, Debug)]
 pub struct KeymapEntry {
+    // Keymap#name
+    pub name: String,
     pub actions: Vec<KeymapAction>,
     pub modifiers: Vec<Modifier>,
-    pub application: Option<OnlyOrNot>,
-    pub title: Option<OnlyOrNot>,
-    pub device: Option<Device>,
-    pub mode: Option<Vec<String>>,
+    pub condition: Option<Condition>,
     pub exact_match: bool,
 }
 
@@ -64,12 +96,10 @@ pub fn build_keymap_table(keymaps: &Vec<Keymap>) -> HashMap<Key, Vec<KeymapEntry
                 None => vec![],
             };
             entries.push(KeymapEntry {
+                name: keymap.name.clone(),
                 actions: actions.to_vec(),
                 modifiers: key_press.modifiers.clone(),
-                application: keymap.application.clone(),
-                title: keymap.window.clone(),
-                device: keymap.device.clone(),
-                mode: keymap.mode.clone(),
+                condition: keymap.condition.clone(),
                 exact_match: keymap.exact_match,
             });
             table.insert(key_press.key, entries);
@@ -2,39 +2,114 @@ use crate::config::key_press::KeyPress;
 use std::collections::HashMap;
 
 use crate::config::remap::Remap;
+use crate::config::suspend::Suspend;
 use evdev::Key;
 use serde::de;
 use serde::{Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
 use std::fmt::Debug;
 use std::time::Duration;
 
 use super::key::parse_key;
+use super::key_press::parse_key_press;
 use super::remap::RemapActions;
 
 // Values in `keymap.remap`
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug)]
 pub enum KeymapAction {
     // Config interface
     KeyPress(KeyPress),
-    #[serde(deserialize_with = "deserialize_remap")]
     Remap(Remap),
-    #[serde(deserialize_with = "deserialize_launch")]
     Launch(Vec<String>),
-    #[serde(deserialize_with = "deserialize_set_mode")]
     SetMode(String),
-    #[serde(deserialize_with = "deserialize_set_mark")]
     SetMark(bool),
-    #[serde(deserialize_with = "deserialize_with_mark")]
     WithMark(KeyPress),
-    #[serde(deserialize_with = "deserialize_escape_next_key")]
     EscapeNextKey(bool),
+    Suspend(Suspend),
+    Resume,
 
     // Internals
-    #[serde(skip)]
     SetExtraModifiers(Vec<Key>),
 }
 
+const KEYMAP_ACTION_KEYS: [&str; 8] = [
+    "remap",
+    "launch",
+    "set_mode",
+    "set_mark",
+    "with_mark",
+    "escape_next_key",
+    "suspend",
+    "resume",
+];
+
+// Not #[serde(untagged)] so that a broken action reports why it's broken
+// instead of "data did not match any variant of untagged enum KeymapAction".
+impl<'de> Deserialize<'de> for KeymapAction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let action = match &value {
+            Value::String(key_press) => parse_key_press(key_press).map(KeymapAction::KeyPress),
+            Value::Mapping(map) => match action_name(map, &KEYMAP_ACTION_KEYS).map_err(de::Error::custom)? {
+                "remap" => deserialize_remap(value).map(KeymapAction::Remap).map_err(From::from),
+                "launch" => deserialize_launch(value).map(KeymapAction::Launch).map_err(From::from),
+                "set_mode" => deserialize_set_mode(value)
+                    .map(KeymapAction::SetMode)
+                    .map_err(From::from),
+                "set_mark" => deserialize_set_mark(value)
+                    .map(KeymapAction::SetMark)
+                    .map_err(From::from),
+                "with_mark" => deserialize_with_mark(value)
+                    .map(KeymapAction::WithMark)
+                    .map_err(From::from),
+                "escape_next_key" => deserialize_escape_next_key(value)
+                    .map(KeymapAction::EscapeNextKey)
+                    .map_err(From::from),
+                "suspend" => deserialize_suspend(value)
+                    .map(KeymapAction::Suspend)
+                    .map_err(From::from),
+                _ => deserialize_resume(value)
+                    .map(|_| KeymapAction::Resume)
+                    .map_err(From::from),
+            },
+            _ => Err(format!("expected a key press like \"C-a\" or a map, got {}", describe_value(&value)).into()),
+        };
+        action.map_err(de::Error::custom)
+    }
+}
+
+// Find which of `names` is used as a key of an action map
+pub fn action_name<'a>(map: &Mapping, names: &[&'a str]) -> Result<&'a str, String> {
+    for name in names {
+        if map.contains_key(*name) {
+            return Ok(name);
+        }
+    }
+    let keys: Vec<String> = map
+        .keys()
+        .map(|key| match key {
+            Value::String(key) => format!("\"{key}\""),
+            key => describe_value(key),
+        })
+        .collect();
+    Err(format!("unknown action with keys [{}], expected one of: {}", keys.join(", "), names.join(", ")))
+}
+
+pub fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(bool) => format!("boolean `{bool}`"),
+        Value::Number(number) => format!("number `{number}`"),
+        Value::String(string) => format!("string \"{string}\""),
+        Value::Sequence(_) => "a list".to_string(),
+        Value::Mapping(_) => "a map".to_string(),
+        Value::Tagged(tagged) => format!("tag `{}`", tagged.tag),
+    }
+}
+
 fn deserialize_remap<'de, D>(deserializer: D) -> Result<Remap, D::Error>
 where
     D: Deserializer<'de>,
@@ -119,14 +194,54 @@ where
     Err(de::Error::custom("not a map with a single \"escape_next_key\" key"))
 }
 
+fn deserialize_suspend<'de, D>(deserializer: D) -> Result<Suspend, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, Suspend>::deserialize(deserializer)?;
+    if let Some(suspend) = action.remove("suspend") {
+        if action.is_empty() {
+            return Ok(suspend);
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"suspend\" key"))
+}
+
+fn deserialize_resume<'de, D>(deserializer: D) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut action = HashMap::<String, bool>::deserialize(deserializer)?;
+    if let Some(true) = action.remove("resume") {
+        if action.is_empty() {
+            return Ok(());
+        }
+    }
+    Err(de::Error::custom("not a map with a single \"resume: true\""))
+}
+
 // Used only for deserializing Vec<Action>
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug)]
 pub enum Actions {
     Action(KeymapAction),
     Actions(Vec<KeymapAction>),
 }
 
+impl<'de> Deserialize<'de> for Actions {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let actions = if value.is_sequence() {
+            Vec::<KeymapAction>::deserialize(value).map(Actions::Actions)
+        } else {
+            KeymapAction::deserialize(value).map(Actions::Action)
+        };
+        actions.map_err(de::Error::custom)
+    }
+}
+
 impl Actions {
     pub fn into_vec(self) -> Vec<KeymapAction> {
         match self {
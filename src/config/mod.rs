@@ -1,6 +1,9 @@
 pub mod application;
+pub mod check;
+pub mod condition;
 pub mod device;
-mod key;
+pub mod escape_chord;
+pub mod key;
 pub mod key_press;
 pub mod keymap;
 pub mod keymap_action;
@@ -8,18 +11,28 @@ mod modmap;
 pub mod modmap_action;
 
 pub mod remap;
+pub mod suspend;
 #[cfg(test)]
 mod tests;
+pub mod window_properties;
 
 extern crate serde_yaml;
 extern crate toml;
 
+use application::{ApplicationMode, ModeMemory};
+use device::Devices;
+use escape_chord::EscapeChord;
 use evdev::Key;
 use keymap::Keymap;
 use modmap::Modmap;
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
-use serde::{Deserialize, Deserializer, de::IgnoredAny};
-use std::{collections::HashMap, error, fs, path::PathBuf, time::SystemTime};
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    error, fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 use self::{
     key::parse_key,
@@ -36,10 +49,23 @@ pub struct Config {
     pub keymap: Vec<Keymap>,
     #[serde(default = "default_mode")]
     pub default_mode: String,
-    #[serde(deserialize_with = "deserialize_virtual_modifiers", default = "Vec::new")]
+    #[serde(default = "Vec::new")]
+    pub application_modes: Vec<ApplicationMode>,
+    // Remember mode and mark_set per application or window, and restore them on focus
+    #[serde(default)]
+    pub mode_memory: Option<ModeMemory>,
+    #[serde(deserialize_with = "deserialize_keys", default = "Vec::new")]
     pub virtual_modifiers: Vec<Key>,
     #[serde(default)]
     pub keypress_delay_ms: u64,
+    // Keys that release every key pressed by xremap when held together
+    #[serde(deserialize_with = "deserialize_keys", default = "Vec::new")]
+    pub panic_chord: Vec<Key>,
+    #[serde(default)]
+    pub escape_chord: EscapeChord,
+    // Devices to remap, overridden by --device, --ignore and --mouse
+    #[serde(default)]
+    pub devices: Devices,
 
     // Data is not used by any part of the application.
     // but can be used with Anchors and Aliases
@@ -66,30 +92,26 @@ fn get_file_ext(filename: &PathBuf) -> ConfigFiletype {
             } else {
                 ConfigFiletype::Yaml
             }
-        },
+        }
         _ => ConfigFiletype::Yaml,
     }
 }
 
 pub fn load_configs(filenames: &Vec<PathBuf>) -> Result<Config, Box<dyn error::Error>> {
     // Assumes filenames is non-empty
-    let config_contents = fs::read_to_string(&filenames[0])?;
+    let mut config = parse_config(&filenames[0])?;
 
-    let mut config: Config = match get_file_ext(&filenames[0]) {
-        ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-        ConfigFiletype::Toml => toml::from_str(&config_contents)?,
-    };
-    
     for filename in &filenames[1..] {
-        let config_contents = fs::read_to_string(&filename)?;
-        let c: Config = match get_file_ext(&filename) {
-            ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents)?,
-            ConfigFiletype::Toml => serde_yaml::from_str(&config_contents)?,
-        };
+        let c = parse_config(filename)?;
 
         config.modmap.extend(c.modmap);
         config.keymap.extend(c.keymap);
+        config.application_modes.extend(c.application_modes);
         config.virtual_modifiers.extend(c.virtual_modifiers);
+        config.devices.include.extend(c.devices.include);
+        config.devices.ignore.extend(c.devices.ignore);
+        config.devices.mouse |= c.devices.mouse;
+        config.devices.options.extend(c.devices.options);
     }
 
     // Timestamp for --watch=config
@@ -101,6 +123,79 @@ pub fn load_configs(filenames: &Vec<PathBuf>) -> Result<Config, Box<dyn error::E
     Ok(config)
 }
 
+fn parse_config(filename: &PathBuf) -> Result<Config, Box<dyn error::Error>> {
+    let config_contents = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+    let result = match get_file_ext(filename) {
+        ConfigFiletype::Yaml => serde_yaml::from_str(&config_contents).map_err(|e| ConfigError::from_yaml(filename, e)),
+        ConfigFiletype::Toml => {
+            toml::from_str(&config_contents).map_err(|e| ConfigError::from_toml(filename, &config_contents, e))
+        }
+    };
+    Ok(result?)
+}
+
+// A config parse error with the position that caused it
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn from_yaml(path: &Path, error: serde_yaml::Error) -> ConfigError {
+        let mut message = error.to_string();
+        let location = error.location();
+        if let Some(location) = &location {
+            // serde_yaml appends the location to the message, which we print separately
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            if let Some(stripped) = message.strip_suffix(&suffix) {
+                message = stripped.to_string();
+            }
+        }
+        ConfigError {
+            path: path.to_path_buf(),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message,
+        }
+    }
+
+    fn from_toml(path: &Path, contents: &str, error: toml::de::Error) -> ConfigError {
+        let (line, column) = match error.span() {
+            Some(span) => {
+                let before = &contents[..span.start];
+                let line = before.matches('\n').count() + 1;
+                let column = before.len() - before.rfind('\n').map_or(0, |pos| pos + 1) + 1;
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        ConfigError {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl error::Error for ConfigError {}
+
 pub fn config_watcher(watch: bool, files: &Vec<PathBuf>) -> anyhow::Result<Option<Inotify>> {
     if watch {
         let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
@@ -121,7 +216,7 @@ fn default_mode() -> String {
     "default".to_string()
 }
 
-fn deserialize_virtual_modifiers<'de, D>(deserializer: D) -> Result<Vec<Key>, D::Error>
+fn deserialize_keys<'de, D>(deserializer: D) -> Result<Vec<Key>, D::Error>
 where
     D: Deserializer<'de>,
 {
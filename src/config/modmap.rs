@@ -5,18 +5,49 @@ use evdev::Key;
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
+use super::condition::{fold_conditions, Condition, LegacyConditions};
 use super::device::Device;
+use super::window_properties::WindowPropertiesMatcher;
 
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[serde(from = "ModmapFields")]
 pub struct Modmap {
-    #[serde(default = "String::new")]
     pub name: String,
-    #[serde(deserialize_with = "deserialize_remap")]
     pub remap: HashMap<Key, ModmapAction>,
-    pub application: Option<OnlyOrNot>,
-    pub window: Option<OnlyOrNot>,
-    pub device: Option<Device>,
+    // `when` and the legacy conditions. None if it always matches.
+    pub condition: Option<Condition>,
+}
+
+// Used only for deserialization
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModmapFields {
+    #[serde(default = "String::new")]
+    name: String,
+    #[serde(deserialize_with = "deserialize_remap")]
+    remap: HashMap<Key, ModmapAction>,
+    application: Option<OnlyOrNot>,
+    window: Option<OnlyOrNot>,
+    window_properties: Option<WindowPropertiesMatcher>,
+    device: Option<Device>,
+    when: Option<Condition>,
+}
+
+impl From<ModmapFields> for Modmap {
+    fn from(fields: ModmapFields) -> Self {
+        let legacy = LegacyConditions {
+            application: &fields.application,
+            window: &fields.window,
+            window_properties: &fields.window_properties,
+            device: &fields.device,
+            mode: &None,
+        };
+        Modmap {
+            condition: fold_conditions(legacy, fields.when),
+            name: fields.name,
+            remap: fields.remap,
+        }
+    }
 }
 
 fn deserialize_remap<'de, D>(deserializer: D) -> Result<HashMap<Key, ModmapAction>, D::Error>
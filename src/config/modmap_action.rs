@@ -1,24 +1,48 @@
-use crate::config::key::deserialize_key;
+use crate::config::key::{deserialize_key, parse_key};
 use evdev::Key;
-use serde::{Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer};
 use serde_with::{serde_as, DurationMilliSeconds};
+use serde_yaml::Value;
 use std::time::Duration;
 
 use super::{
-    deserialize_virtual_modifiers,
-    keymap_action::{Actions, KeymapAction},
+    deserialize_keys,
+    keymap_action::{action_name, describe_value, Actions, KeymapAction},
 };
 
 // Values in `modmap.remap`
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug)]
 pub enum ModmapAction {
-    #[serde(deserialize_with = "deserialize_key")]
     Key(Key),
     MultiPurposeKey(MultiPurposeKey),
     PressReleaseKey(PressReleaseKey),
 }
 
+// Not #[serde(untagged)] so that a broken action reports why it's broken
+impl<'de> Deserialize<'de> for ModmapAction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let action = match &value {
+            Value::String(key) => parse_key(key).map(ModmapAction::Key),
+            Value::Mapping(map) => {
+                match action_name(map, &["held", "alone", "press", "release"]).map_err(de::Error::custom)? {
+                    "held" | "alone" => MultiPurposeKey::deserialize(value)
+                        .map(ModmapAction::MultiPurposeKey)
+                        .map_err(From::from),
+                    _ => PressReleaseKey::deserialize(value)
+                        .map(ModmapAction::PressReleaseKey)
+                        .map_err(From::from),
+                }
+            }
+            _ => Err(format!("expected a key name or a map, got {}", describe_value(&value)).into()),
+        };
+        action.map_err(de::Error::custom)
+    }
+}
+
 #[serde_as]
 #[derive(Clone, Debug, Deserialize)]
 pub struct MultiPurposeKey {
@@ -32,22 +56,34 @@ pub struct MultiPurposeKey {
 #[derive(Clone, Debug, Deserialize)]
 pub struct PressReleaseKey {
     #[serde(default)]
-    pub skip_key_event: bool ,
+    pub skip_key_event: bool,
     #[serde(deserialize_with = "deserialize_actions")]
     pub press: Vec<KeymapAction>,
     #[serde(deserialize_with = "deserialize_actions")]
     pub release: Vec<KeymapAction>,
 }
 // Used only for deserializing Vec<Keys>
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug)]
 pub enum Keys {
-    #[serde(deserialize_with = "deserialize_key")]
     Key(Key),
-    #[serde(deserialize_with = "deserialize_virtual_modifiers")]
     Keys(Vec<Key>),
 }
 
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let keys = if value.is_sequence() {
+            deserialize_keys(value).map(Keys::Keys)
+        } else {
+            deserialize_key(value).map(Keys::Key)
+        };
+        keys.map_err(de::Error::custom)
+    }
+}
+
 impl Keys {
     pub fn into_vec(self) -> Vec<Key> {
         match self {
@@ -0,0 +1,40 @@
+use serde::{de, Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::time::Duration;
+
+// { suspend: true } or { suspend: { ungrab: true, timeout_millis: 60000 } }
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Suspend {
+    // Ungrab devices instead of passing events through
+    pub ungrab: bool,
+    // Resume automatically after this, or once the keys held then are released
+    pub timeout: Option<Duration>,
+}
+
+// Used only for deserialization
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SuspendOptions {
+    #[serde(default)]
+    ungrab: bool,
+    timeout_millis: Option<u64>,
+}
+
+impl<'de> Deserialize<'de> for Suspend {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Bool(true) => Ok(Suspend::default()),
+            value @ Value::Mapping(_) => {
+                let options = SuspendOptions::deserialize(value).map_err(de::Error::custom)?;
+                Ok(Suspend {
+                    ungrab: options.ungrab,
+                    timeout: options.timeout_millis.map(Duration::from_millis),
+                })
+            }
+            _ => Err(de::Error::custom("expected true or a map with ungrab and timeout_millis")),
+        }
+    }
+}
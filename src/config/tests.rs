@@ -1,5 +1,8 @@
-use crate::Config;
+use crate::config::escape_chord::EscapeChordAction;
+use crate::config::Config;
+use evdev::Key;
 use indoc::indoc;
+use std::time::Duration;
 
 extern crate serde_yaml;
 extern crate toml;
@@ -90,6 +93,62 @@ fn test_yaml_virtual_modifiers() {
     "})
 }
 
+#[test]
+fn test_yaml_panic_chord() {
+    yaml_assert_parse(indoc! {"
+    panic_chord: [Shift_L, Shift_R, Backspace]
+    "})
+}
+
+#[test]
+fn test_yaml_escape_chord() {
+    let config: Config = serde_yaml::from_str(indoc! {"
+    escape_chord:
+      keys: [Ctrl_L, Alt_L, Backspace]
+      hold_millis: 1000
+      action: pause
+    "})
+    .unwrap();
+    assert_eq!(vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTALT, Key::KEY_BACKSPACE], config.escape_chord.keys);
+    assert_eq!(Duration::from_millis(1000), config.escape_chord.hold);
+    assert_eq!(EscapeChordAction::Pause, config.escape_chord.action);
+
+    // It's enabled by default
+    let config: Config = serde_yaml::from_str("{}").unwrap();
+    assert_eq!(EscapeChordAction::Exit, config.escape_chord.action);
+    assert_eq!(3, config.escape_chord.keys.len());
+}
+
+#[test]
+fn test_yaml_application_modes() {
+    yaml_assert_parse(indoc! {"
+    application_modes:
+      - only: [Emacs, /^Gvim/]
+        mode: emacs
+      - not: Firefox
+        mode: normal
+    mode_memory: window
+    "});
+}
+
+#[test]
+fn test_yaml_suspend() {
+    yaml_assert_parse(indoc! {"
+    keymap:
+      - remap:
+          C-M-s: { suspend: true }
+          C-M-u: { suspend: { ungrab: true, timeout_millis: 60000 } }
+          C-M-r: { resume: true }
+    "});
+    let error = serde_yaml::from_str::<Config>(indoc! {"
+    keymap:
+      - remap:
+          C-M-s: { suspend: { timeout: 60000 } }
+    "})
+    .unwrap_err();
+    assert!(error.to_string().contains("unknown field `timeout`"), "{error}");
+}
+
 #[test]
 fn test_yaml_modmap_press_release_key() {
     yaml_assert_parse(indoc! {r#"
@@ -523,6 +582,33 @@ fn test_toml_fail_on_data_outside_of_config_model() {
     "})
 }
 
+#[test]
+fn test_yaml_unknown_keymap_action_error() {
+    yaml_assert_error(
+        indoc! {"
+        keymap:
+          - remap:
+              C-a: { lanch: [foo] }
+        "},
+        "keymap[0].remap: unknown action with keys [\"lanch\"], expected one of: \
+         remap, launch, set_mode, set_mark, with_mark, escape_next_key, suspend, resume at line 3 column 7",
+    )
+}
+
+#[test]
+fn test_yaml_unknown_modmap_key_error() {
+    yaml_assert_error(
+        indoc! {"
+        modmap:
+          - remap:
+              CapsLock:
+                held: Ctrl_L
+                alone: Escp
+        "},
+        "modmap[0].remap: unknown key 'Escp' at line 3 column 7",
+    )
+}
+
 fn toml_assert_parse(toml: &str) {
     let result: Result<Config, toml::de::Error> = toml::from_str(toml);
     if let Err(e) = result {
@@ -536,3 +622,10 @@ fn yaml_assert_parse(yaml: &str) {
         panic!("{}", e)
     }
 }
+
+fn yaml_assert_error(yaml: &str, expected: &str) {
+    match serde_yaml::from_str::<Config>(yaml) {
+        Ok(_) => panic!("expected an error: {}", expected),
+        Err(e) => assert_eq!(expected, e.to_string()),
+    }
+}
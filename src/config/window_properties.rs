@@ -0,0 +1,18 @@
+use crate::config::application::OnlyOrNot;
+use serde::Deserialize;
+
+// keymap/modmap `window_properties`: Properties of the focused window other than application and window.
+// Folded into Condition on deserialization.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WindowPropertiesMatcher {
+    // The path or the file name of the window's executable
+    pub executable: Option<OnlyOrNot>,
+    pub workspace: Option<OnlyOrNot>,
+    pub output: Option<OnlyOrNot>,
+    pub floating: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub xwayland: Option<bool>,
+    pub resource_name: Option<OnlyOrNot>,
+    pub resource_class: Option<OnlyOrNot>,
+}
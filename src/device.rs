@@ -1,10 +1,14 @@
 extern crate evdev;
 extern crate nix;
 
+use crate::config::device::{Capability, DeviceMatcher, DeviceOptions, Devices};
 use anyhow::bail;
 use derive_where::derive_where;
 use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
-use evdev::{AttributeSet, BusType, Device, FetchEventsSynced, InputId, Key, RelativeAxisType};
+use evdev::{
+    AbsoluteAxisType, AttributeSet, BusType, Device, FetchEventsSynced, InputEvent, InputId, Key, PropType,
+    RelativeAxisType,
+};
 use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use std::collections::HashMap;
 use std::error::Error;
@@ -39,8 +43,16 @@ static MOUSE_BTNS: [&str; 20] = [
 
 static mut DEVICE_NAME: Option<String> = None;
 
+// Between the name of the default output device and an output of devices.options
+const OUTPUT_SEPARATOR: &str = " output=";
+
 // Credit: https://github.com/mooz/xkeysnail/blob/bf3c93b4fe6efd42893db4e6588e5ef1c4909cfb/xkeysnail/output.py#L10-L32
-pub fn output_device(bus_type: Option<BusType>, mouse: bool) -> Result<VirtualDevice, Box<dyn Error>> {
+// `output` names an output device of devices.options, which is named after the default one.
+pub fn output_device(
+    bus_type: Option<BusType>,
+    mouse: bool,
+    output: Option<&str>,
+) -> Result<VirtualDevice, Box<dyn Error>> {
     let mut keys: AttributeSet<Key> = AttributeSet::new();
     for code in Key::KEY_RESERVED.code()..Key::BTN_TRIGGER_HAPPY40.code() {
         let key = Key::new(code);
@@ -62,50 +74,156 @@ pub fn output_device(bus_type: Option<BusType>, mouse: bool) -> Result<VirtualDe
     let device = VirtualDeviceBuilder::new()?
         // These are taken from https://docs.rs/evdev/0.12.0/src/evdev/uinput.rs.html#183-188
         .input_id(InputId::new(bus_type.unwrap_or(BusType::BUS_USB), 0x1234, 0x5678, 0x111))
-        .name(&InputDevice::current_name())
+        .name(&match output {
+            Some(output) => format!("{}{OUTPUT_SEPARATOR}{output}", InputDevice::current_name()),
+            None => InputDevice::current_name().to_string(),
+        })
         .with_keys(&keys)?
         .with_relative_axes(&relative_axes)?
         .build()?;
     Ok(device)
 }
 
+// Where ActionDispatcher emits events. VirtualDevice is the real one.
+pub trait OutputDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()>;
+}
+
+impl OutputDevice for VirtualDevice {
+    fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        VirtualDevice::emit(self, events)
+    }
+}
+
 pub fn device_watcher(watch: bool) -> anyhow::Result<Option<Inotify>> {
     if watch {
         let inotify = Inotify::init(InitFlags::IN_NONBLOCK)?;
         inotify.add_watch("/dev/input", AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ATTRIB)?;
+        // udev creates links after the device, by renaming them into place. Select the device again then.
+        // The directories don't exist until a device has such a link.
+        for dir in LINK_DIRS {
+            let _ = inotify.add_watch(dir, AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO);
+        }
         Ok(Some(inotify))
     } else {
         Ok(None)
     }
 }
 
+// Which devices to remap and how: `devices` of the config, overridden by --device, --ignore and --mouse
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceSelection {
+    pub include: Vec<DeviceMatcher>,
+    pub ignore: Vec<DeviceMatcher>,
+    pub mouse: bool,
+    pub options: Vec<DeviceOptions>,
+}
+
+impl DeviceSelection {
+    pub fn new(devices: &Devices, include: &[DeviceMatcher], ignore: &[DeviceMatcher], mouse: Option<bool>) -> Self {
+        let choose = |cli: &[DeviceMatcher], config: &Vec<DeviceMatcher>| {
+            if cli.is_empty() {
+                config.clone()
+            } else {
+                cli.to_vec()
+            }
+        };
+        DeviceSelection {
+            include: choose(include, &devices.include),
+            ignore: choose(ignore, &devices.ignore),
+            mouse: mouse.unwrap_or(devices.mouse),
+            options: devices.options.clone(),
+        }
+    }
+
+    // Output devices to create for the options
+    pub fn outputs(&self) -> Vec<&str> {
+        let mut outputs: Vec<&str> = self
+            .options
+            .iter()
+            .filter_map(|options| options.output.as_deref())
+            .collect();
+        outputs.sort();
+        outputs.dedup();
+        outputs
+    }
+
+    // Whether to remap a device other than the output devices, which is a keyboard and/or a mouse
+    pub fn includes(&self, info: &InputDeviceInfo, keyboard: bool, mouse: bool) -> bool {
+        (if self.include.is_empty() {
+            keyboard || (self.mouse && mouse)
+        } else {
+            self.include.iter().any(|matcher| info.matches(matcher))
+        }) && !self.ignore.iter().any(|matcher| info.matches(matcher))
+    }
+
+    // The first devices.options matching the device
+    pub fn options_for(&self, info: &InputDeviceInfo) -> DeviceOptions {
+        self.options
+            .iter()
+            .find(|options| options.device.iter().any(|matcher| info.matches(matcher)))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// An input device as the event loop sees it. InputDevice is the real one.
+pub trait InputSource {
+    fn path(&self) -> &Path;
+    fn grab(&mut self) -> bool;
+    fn ungrab(&mut self);
+    fn fetch_events(&mut self) -> io::Result<Vec<InputEvent>>;
+    // Whether to remap the device. It's also grabbed if `grab`, unless devices.options say otherwise.
+    fn select(&mut self, selection: &DeviceSelection, grab: bool) -> bool;
+    fn output(&self) -> Option<&str>;
+    fn bus_type(&self) -> BusType;
+    fn to_info(&self) -> InputDeviceInfo<'_>;
+    fn print(&self);
+}
+
 pub fn get_input_devices(
-    device_opts: &[String],
-    ignore_opts: &[String],
-    mouse: bool,
+    selection: &DeviceSelection,
     watch: bool,
+    grab: bool,
 ) -> anyhow::Result<HashMap<PathBuf, InputDevice>> {
-    let mut devices: Vec<_> = InputDevice::devices()?.collect();
-    devices.sort();
+    select_devices(InputDevice::devices()?.collect(), selection, watch, grab)
+}
+
+// Choose devices to remap out of all the devices
+pub fn select_devices<D: InputSource>(
+    mut devices: Vec<D>,
+    selection: &DeviceSelection,
+    watch: bool,
+    grab: bool,
+) -> anyhow::Result<HashMap<PathBuf, D>> {
+    let DeviceSelection {
+        include: device_opts,
+        ignore: ignore_opts,
+        mouse,
+        ..
+    } = selection;
+    devices.sort_by(|a, b| a.path().cmp(b.path()));
 
     println!("Selecting devices from the following list:");
     println!("{}", SEPARATOR);
-    devices.iter().for_each(InputDevice::print);
+    devices.iter().for_each(D::print);
     println!("{}", SEPARATOR);
 
     if device_opts.is_empty() {
-        if mouse {
-            print!("Selected keyboards and mice automatically since --device options weren't specified");
+        if *mouse {
+            print!(
+                "Selected keyboards and mice automatically since neither --device nor devices.include was specified"
+            );
         } else {
-            print!("Selected keyboards automatically since --device options weren't specified");
+            print!("Selected keyboards automatically since neither --device nor devices.include was specified");
         }
     } else {
-        print!("Selected devices matching {:?}", device_opts);
+        print!("Selected devices matching {:?}", display_matchers(device_opts));
     };
     if ignore_opts.is_empty() {
         println!(":")
     } else {
-        println!(", ignoring {:?}:", ignore_opts);
+        println!(", ignoring {:?}:", display_matchers(ignore_opts));
     }
 
     let devices: Vec<_> = devices
@@ -114,7 +232,7 @@ pub fn get_input_devices(
         // alternative is `Vec::retain_mut` whenever that gets stabilized
         .filter_map(|mut device| {
             // filter out any not matching devices and devices that error on grab
-            (device.is_input_device(device_opts, ignore_opts, mouse) && device.grab()).then(|| device)
+            device.select(selection, grab).then_some(device)
         })
         .collect();
 
@@ -126,24 +244,82 @@ pub fn get_input_devices(
             bail!("No device was selected!");
         }
     } else {
-        devices.iter().for_each(InputDevice::print);
+        devices.iter().for_each(D::print);
     }
     println!("{}", SEPARATOR);
 
-    Ok(devices.into_iter().map(From::from).collect())
+    Ok(devices
+        .into_iter()
+        .map(|device| (device.path().to_path_buf(), device))
+        .collect())
+}
+
+pub const LINK_DIRS: [&str; 2] = ["/dev/input/by-id", "/dev/input/by-path"];
+
+// The /dev/input/by-id and by-path symlinks pointing to a device
+fn device_links(path: &Path) -> Vec<PathBuf> {
+    device_links_in(&LINK_DIRS.map(Path::new), path)
+}
+
+pub fn device_links_in(dirs: &[&Path], path: &Path) -> Vec<PathBuf> {
+    dirs.iter()
+        .filter_map(|dir| read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|link| link.canonicalize().is_ok_and(|target| target == path))
+        .collect()
+}
+
+// Print matchers like the strings they were parsed from
+fn display_matchers(matchers: &[DeviceMatcher]) -> Vec<String> {
+    matchers.iter().map(ToString::to_string).collect()
 }
 
 #[derive(Debug)]
 pub struct InputDeviceInfo<'a> {
     pub name: &'a str,
     pub path: &'a Path,
+    pub id: InputId,
+    pub phys: Option<&'a str>,
+    pub uniq: Option<&'a str>,
+    // /dev/input/by-id and by-path symlinks to the device
+    pub links: &'a [PathBuf],
+    pub capabilities: &'a [Capability],
 }
 
 impl<'a> InputDeviceInfo<'a> {
-    pub fn matches(&self, filter: &String) -> bool {
-        let filter = filter.as_str();
+    // A device known only by its name and path, e.g. in --simulate
+    pub fn new(name: &'a str, path: &'a Path) -> Self {
+        InputDeviceInfo {
+            name,
+            path,
+            id: InputId::new(BusType(0), 0, 0, 0),
+            phys: None,
+            uniq: None,
+            links: &[],
+            capabilities: &[],
+        }
+    }
+
+    pub fn matches(&self, filter: &DeviceMatcher) -> bool {
+        let filter = match filter {
+            DeviceMatcher::Name(name) => name.as_str(),
+            DeviceMatcher::Pattern(matcher) => {
+                return matcher.matches(self.name)
+                    || self
+                        .paths()
+                        .any(|path| path.to_str().is_some_and(|path| matcher.matches(path)));
+            }
+            DeviceMatcher::Id(vendor, product) => {
+                return self.id.vendor() == *vendor && product.is_none_or(|product| self.id.product() == product);
+            }
+            DeviceMatcher::Bus(bus) => return self.id.bus_type() == *bus,
+            DeviceMatcher::Phys(matcher) => return self.phys.is_some_and(|phys| matcher.matches(phys)),
+            DeviceMatcher::Uniq(matcher) => return self.uniq.is_some_and(|uniq| matcher.matches(uniq)),
+            DeviceMatcher::Capability(capability) => return self.capabilities.contains(capability),
+        };
         // Check exact matches for explicit selection
-        if self.path.as_os_str() == filter || self.name == filter {
+        if self.paths().any(|path| path.as_os_str() == filter) || self.name == filter {
             return true;
         }
         // eventXX shorthand for /dev/input/eventXX
@@ -156,6 +332,10 @@ impl<'a> InputDeviceInfo<'a> {
         }
         return false;
     }
+
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.path).chain(self.links.iter().map(PathBuf::as_path))
+    }
 }
 
 #[derive_where(PartialEq, PartialOrd, Ord)]
@@ -163,6 +343,17 @@ pub struct InputDevice {
     path: PathBuf,
     #[derive_where(skip)]
     device: Device,
+    // Resolved by select(), since udev creates them after the device appears
+    #[derive_where(skip)]
+    links: Vec<PathBuf>,
+    #[derive_where(skip)]
+    capabilities: Vec<Capability>,
+    // devices.options.grab
+    #[derive_where(skip)]
+    grab_enabled: bool,
+    // devices.options.output
+    #[derive_where(skip)]
+    output: Option<String>,
 }
 
 impl Eq for InputDevice {}
@@ -175,22 +366,22 @@ impl TryFrom<PathBuf> for InputDevice {
             .file_name()
             .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
         if fname.as_bytes().starts_with(b"event") {
-            Ok(Self {
+            let mut device = Self {
                 device: Device::open(&path)?,
+                links: vec![],
+                capabilities: vec![],
+                grab_enabled: true,
+                output: None,
                 path,
-            })
+            };
+            device.capabilities = device.capabilities();
+            Ok(device)
         } else {
             Err(io::ErrorKind::InvalidInput.into())
         }
     }
 }
 
-impl From<InputDevice> for (PathBuf, InputDevice) {
-    fn from(device: InputDevice) -> Self {
-        (device.path.clone(), device)
-    }
-}
-
 impl AsRawFd for InputDevice {
     fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
         self.device.as_raw_fd()
@@ -200,6 +391,9 @@ impl AsRawFd for InputDevice {
 /// Device Wrappers Abstractions
 impl InputDevice {
     pub fn grab(&mut self) -> bool {
+        if !self.grab_enabled {
+            return true;
+        }
         if let Err(error) = self.device.grab() {
             println!("Failed to grab device '{}' at '{}' due to: {error}", self.device_name(), self.path.display());
             false
@@ -222,6 +416,10 @@ impl InputDevice {
         self.device.name().unwrap_or("<Unnamed device>")
     }
 
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
     pub fn bus_type(&self) -> BusType {
         self.device.input_id().bus_type()
     }
@@ -230,24 +428,46 @@ impl InputDevice {
         InputDeviceInfo {
             name: self.device_name(),
             path: &self.path,
+            id: self.device.input_id(),
+            phys: self.device.physical_path(),
+            uniq: self.device.unique_name(),
+            links: &self.links,
+            capabilities: &self.capabilities,
         }
     }
 }
 
 impl InputDevice {
-    pub fn is_input_device(&self, device_filter: &[String], ignore_filter: &[String], mouse: bool) -> bool {
-        if self.device_name() == Self::current_name() {
+    // Whether to remap the device. It's also grabbed if `grab`, unless devices.options say otherwise.
+    pub fn select(&mut self, selection: &DeviceSelection, grab: bool) -> bool {
+        // Devices are selected at startup and on --watch=device events, when their links may have changed.
+        // Matching them later, e.g. for `device` of a keymap, uses what's resolved here without I/O.
+        self.links = device_links(&self.path);
+        if !self.is_input_device(selection) {
             return false;
         }
-        (if device_filter.is_empty() {
-            self.is_keyboard() || (mouse && self.is_mouse())
-        } else {
-            self.matches_any(device_filter)
-        }) && (ignore_filter.is_empty() || !self.matches_any(ignore_filter))
+        let options = selection.options_for(&self.to_info());
+        self.grab_enabled = options.grab;
+        self.output = options.output;
+        !grab || self.grab()
+    }
+
+    fn is_input_device(&self, selection: &DeviceSelection) -> bool {
+        !self.is_own_device() && selection.includes(&self.to_info(), self.is_keyboard(), self.is_mouse())
+    }
+
+    // The output devices of this process
+    fn is_own_device(&self) -> bool {
+        let name = self.device_name();
+        let current_name = Self::current_name();
+        name == current_name
+            || name
+                .strip_prefix(current_name)
+                .is_some_and(|output| output.starts_with(OUTPUT_SEPARATOR))
     }
 
     // We can't know the device path from evdev::enumerate(). So we re-implement it.
-    fn devices() -> io::Result<impl Iterator<Item = InputDevice>> {
+    pub fn devices() -> io::Result<impl Iterator<Item = InputDevice>> {
         Ok(read_dir("/dev/input")?.filter_map(|entry| {
             // Allow "Permission denied" when opening the current process's own device.
             InputDevice::try_from(entry.ok()?.path()).ok()
@@ -278,14 +498,6 @@ impl InputDevice {
             .any(|device| return device.device_name().contains(device_name))
     }
 
-    fn matches_any(&self, filter: &[String]) -> bool {
-        // Force unmatch its own device
-        if self.device_name() == Self::current_name() {
-            return false;
-        }
-        return filter.iter().any(|f| self.to_info().matches(f));
-    }
-
     fn is_keyboard(&self) -> bool {
         // Credit: https://github.com/mooz/xkeysnail/blob/bf3c93b4fe6efd42893db4e6588e5ef1c4909cfb/xkeysnail/input.py#L17-L32
         match self.device.supported_keys() {
@@ -306,8 +518,76 @@ impl InputDevice {
             .map_or(false, |keys| keys.contains(Key::BTN_LEFT))
     }
 
+    fn capabilities(&self) -> Vec<Capability> {
+        let has_key = |key| self.device.supported_keys().is_some_and(|keys| keys.contains(key));
+        let has_position = self
+            .device
+            .supported_absolute_axes()
+            .is_some_and(|axes| axes.contains(AbsoluteAxisType::ABS_X));
+        // Like libinput, tell touchscreens from touchpads by INPUT_PROP_DIRECT
+        let direct = self.device.properties().contains(PropType::DIRECT);
+        [
+            (Capability::Keyboard, self.is_keyboard()),
+            (Capability::Mouse, self.is_mouse() && !has_position),
+            (Capability::Touchpad, has_key(Key::BTN_TOOL_FINGER) && has_position && !direct),
+            (Capability::Touchscreen, has_position && direct),
+        ]
+        .into_iter()
+        .filter_map(|(capability, has)| has.then_some(capability))
+        .collect()
+    }
+
     pub fn print(&self) {
-        println!("{:18}: {}", self.path.display(), self.device_name())
+        let mut options = vec![];
+        if let Some(output) = &self.output {
+            options.push(format!("output: {output}"));
+        }
+        if !self.grab_enabled {
+            options.push("not grabbed".to_string());
+        }
+        if options.is_empty() {
+            println!("{:18}: {}", self.path.display(), self.device_name())
+        } else {
+            println!("{:18}: {} ({})", self.path.display(), self.device_name(), options.join(", "))
+        }
+    }
+}
+
+impl InputSource for InputDevice {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn grab(&mut self) -> bool {
+        InputDevice::grab(self)
+    }
+
+    fn ungrab(&mut self) {
+        InputDevice::ungrab(self)
+    }
+
+    fn fetch_events(&mut self) -> io::Result<Vec<InputEvent>> {
+        Ok(InputDevice::fetch_events(self)?.collect())
+    }
+
+    fn select(&mut self, selection: &DeviceSelection, grab: bool) -> bool {
+        InputDevice::select(self, selection, grab)
+    }
+
+    fn output(&self) -> Option<&str> {
+        InputDevice::output(self)
+    }
+
+    fn bus_type(&self) -> BusType {
+        InputDevice::bus_type(self)
+    }
+
+    fn to_info(&self) -> InputDeviceInfo<'_> {
+        InputDevice::to_info(self)
+    }
+
+    fn print(&self) {
+        InputDevice::print(self)
     }
 }
 
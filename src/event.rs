@@ -11,7 +11,7 @@ pub enum Event<'a> {
     RelativeEvent(InputDeviceInfo<'a>, RelativeEvent),
     // Any other InputEvent type sent from evdev
     OtherEvents(InputEvent),
-    // Timer for nested override reached its timeout
+    // Timer for nested override or suspend reached its timeout
     OverrideTimeout,
 }
 
@@ -1,22 +1,29 @@
 use crate::action::Action;
-use crate::client::WMClient;
-use crate::config::application::OnlyOrNot;
+use crate::client::{WMClient, WindowProperties};
+use crate::clock::Clock;
+use crate::config::application::{ApplicationMatcher, ModeMemory};
+use crate::config::check::set_modes;
+use crate::config::condition::Condition;
 use crate::config::key_press::{KeyPress, Modifier};
 use crate::config::keymap::{build_override_table, OverrideEntry};
 use crate::config::keymap_action::KeymapAction;
 use crate::config::modmap_action::{Keys, ModmapAction, MultiPurposeKey, PressReleaseKey};
 use crate::config::remap::Remap;
+use crate::config::suspend::Suspend;
+use crate::config::Config;
 use crate::device::InputDeviceInfo;
 use crate::event::{Event, KeyEvent, RelativeEvent};
-use crate::{config, Config};
 use evdev::Key;
 use lazy_static::lazy_static;
 use log::debug;
 use nix::sys::time::TimeSpec;
 use nix::sys::timerfd::{Expiration, TimerFd, TimerSetTimeFlags};
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
 use std::error::Error;
+use std::io;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 // This const is a value used to offset RELATIVE events' scancodes
@@ -36,16 +43,25 @@ pub struct EventHandler {
     application_client: WMClient,
     application_cache: Option<String>,
     title_cache: Option<String>,
+    properties_cache: Option<WindowProperties>,
     // State machine for multi-purpose keys
     multi_purpose_keys: HashMap<Key, MultiPurposeKeyState>,
     // Current nested remaps
     override_remaps: Vec<HashMap<Key, Vec<OverrideEntry>>>,
     // Key triggered on a timeout of nested remaps
     override_timeout_key: Option<Key>,
-    // Trigger a timeout of nested remaps through select(2)
-    override_timer: TimerFd,
+    // Trigger a timeout of nested remaps or suspend through select(2)
+    timer: Box<dyn Timer>,
+    // The time of alone_timeout and suspend's timeout
+    clock: Box<dyn Clock>,
     // { set_mode: String }
     mode: String,
+    // The application (and window for mode_memory: window) that application_modes and mode_memory were last applied for
+    focus: Option<String>,
+    // The mode to restore when the application of application_modes loses focus
+    mode_before_application: Option<String>,
+    // mode and mark_set of recent focuses for mode_memory
+    mode_memories: ModeMemories,
     // { set_mark: true }
     mark_set: bool,
     // { escape_next_key: true }
@@ -54,6 +70,85 @@ pub struct EventHandler {
     keypress_delay: Duration,
     // Buffered actions to be dispatched. TODO: Just return actions from each function instead of using this.
     actions: Vec<Action>,
+    // Names of the matched modmap/keymap entries for `xremap inspect`. None unless it's inspecting.
+    matched_entries: Option<Vec<String>>,
+    // { suspend: .. }. Remapping is suspended while it's Some.
+    suspension: Option<Suspension>,
+}
+
+// Fires Event::OverrideTimeout after the timeout of nested remaps or suspend. TimerFd is the real one, which select(2) waits for.
+pub trait Timer {
+    fn set(&mut self, timeout: Duration) -> io::Result<()>;
+    fn unset(&mut self) -> io::Result<()>;
+}
+
+impl Timer for TimerFd {
+    fn set(&mut self, timeout: Duration) -> io::Result<()> {
+        let expiration = Expiration::OneShot(TimeSpec::from_duration(timeout));
+        Ok(TimerFd::set(self, expiration, TimerSetTimeFlags::empty())?)
+    }
+
+    fn unset(&mut self) -> io::Result<()> {
+        Ok(TimerFd::unset(self)?)
+    }
+}
+
+struct Suspension {
+    ungrab: bool,
+    resume_at: Option<Instant>,
+    // Physical keys held while suspended, which hold off the timeout
+    pressed_keys: HashSet<Key>,
+}
+
+impl Suspension {
+    // Whether the timeout has passed with no key held
+    fn timed_out(&self, now: Instant) -> bool {
+        self.pressed_keys.is_empty() && self.resume_at.is_some_and(|resume_at| now >= resume_at)
+    }
+}
+
+// Window titles change often, e.g. in browsers and terminals, so only this many focuses are remembered
+const MODE_MEMORY_CAPACITY: usize = 256;
+
+// mode and mark_set of each focus for mode_memory, least recently left first
+#[derive(Default)]
+struct ModeMemories {
+    entries: VecDeque<(String, (String, bool))>,
+}
+
+impl ModeMemories {
+    fn remember(&mut self, focus: String, state: (String, bool)) {
+        self.entries.retain(|(f, _)| *f != focus);
+        if self.entries.len() >= MODE_MEMORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((focus, state));
+    }
+
+    fn recall(&self, focus: &str) -> Option<(String, bool)> {
+        self.entries
+            .iter()
+            .find(|(f, _)| f == focus)
+            .map(|(_, state)| state.clone())
+    }
+
+    fn retain(&mut self, f: impl Fn(&(String, bool)) -> bool) {
+        self.entries.retain(|(_, state)| f(state));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// How EventHandler wants input devices to be handled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuspendState {
+    Active,
+    // Devices are grabbed, and events are sent as is
+    PassThrough,
+    // Devices should be ungrabbed, and events are ignored
+    Ungrabbed,
 }
 
 struct TaggedAction {
@@ -62,7 +157,13 @@ struct TaggedAction {
 }
 
 impl EventHandler {
-    pub fn new(timer: TimerFd, mode: &str, keypress_delay: Duration, application_client: WMClient) -> EventHandler {
+    pub fn new(
+        timer: Box<dyn Timer>,
+        clock: Box<dyn Clock>,
+        mode: &str,
+        keypress_delay: Duration,
+        application_client: WMClient,
+    ) -> EventHandler {
         EventHandler {
             modifiers: HashSet::new(),
             extra_modifiers: HashSet::new(),
@@ -70,23 +171,147 @@ impl EventHandler {
             application_client,
             application_cache: None,
             title_cache: None,
+            properties_cache: None,
             multi_purpose_keys: HashMap::new(),
             override_remaps: vec![],
             override_timeout_key: None,
-            override_timer: timer,
+            timer,
+            clock,
             mode: mode.to_string(),
+            focus: None,
+            mode_before_application: None,
+            mode_memories: ModeMemories::default(),
             mark_set: false,
             escape_next_key: false,
             keypress_delay,
             actions: vec![],
+            matched_entries: None,
+            suspension: None,
         }
     }
 
-    // Handle an Event and return Actions. This should be the only public method of EventHandler.
+    // Start collecting the names of matched modmap/keymap entries for take_matched_entries
+    pub fn inspect_matches(&mut self) {
+        self.matched_entries = Some(vec![]);
+    }
+
+    // Names of the modmap/keymap entries matched since the last call
+    pub fn take_matched_entries(&mut self) -> Vec<String> {
+        self.matched_entries.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // The currently focused application and window title, bypassing the cache
+    pub fn focused_window(&mut self) -> (Option<String>, Option<String>) {
+        (self.application_client.current_application(), self.application_client.current_window())
+    }
+
+    // Migrate the state to a reloaded config, and return Actions to release keys that would otherwise get stuck.
+    // pressed_keys is kept as is, so that held keys are still released as what they were pressed as.
+    pub fn on_config_reload(&mut self, config: &Config) -> Result<Vec<Action>, Box<dyn Error>> {
+        self.keypress_delay = Duration::from_millis(config.keypress_delay_ms);
+        let modes = set_modes(config);
+        if !modes.contains(self.mode.as_str()) {
+            self.mode = config.default_mode.clone();
+            println!("mode: {}", self.mode);
+        }
+        if let Some(mode) = &mut self.mode_before_application {
+            if !modes.contains(mode.as_str()) {
+                *mode = config.default_mode.clone();
+            }
+        }
+        // Apply the new application_modes on the next key press, restoring the current state if it's remembered
+        if let Some(focus) = self.focus.take() {
+            if config.mode_memory.is_some() {
+                self.mode_memories.remember(focus, (self.mode.clone(), self.mark_set));
+            }
+        }
+        if config.mode_memory.is_none() {
+            self.mode_memories.clear();
+        }
+        self.mode_memories.retain(|(mode, _)| modes.contains(mode.as_str()));
+        // Nested remaps may come from removed keymaps
+        self.remove_override()?;
+
+        // Keys that are no longer modifiers would never be released from self.modifiers
+        self.modifiers
+            .retain(|key| MODIFIER_KEYS.contains(key) || config.virtual_modifiers.contains(key));
+
+        // A release of a key that is no longer a multi-purpose key would not release its held keys
+        let removed_keys: Vec<Key> = self
+            .multi_purpose_keys
+            .keys()
+            .filter(|key| !is_multi_purpose_key(config, key))
+            .copied()
+            .collect();
+        for key in removed_keys {
+            let state = self
+                .multi_purpose_keys
+                .remove(&key)
+                .expect("key is in multi_purpose_keys");
+            // Nothing has been pressed yet if the press is still delayed
+            if state.alone_timeout_at.is_none() {
+                let mut keys = state.held.into_vec();
+                keys.sort_by(modifiers_last);
+                self.send_keys(&keys, RELEASE);
+            }
+        }
+        Ok(self.actions.drain(..).collect())
+    }
+
+    // Stop remapping until resume() or the timeout, which the timer fires. Keys pressed by remapping are left to
+    // ActionDispatcher#release_all, so the state for them is just dropped here.
+    pub fn suspend(&mut self, suspend: &Suspend) -> Result<(), Box<dyn Error>> {
+        self.remove_override()?;
+        self.pressed_keys.clear();
+        self.multi_purpose_keys.clear();
+        self.extra_modifiers.clear();
+        self.escape_next_key = false;
+        if let Some(timeout) = suspend.timeout {
+            self.timer.set(timeout)?;
+        }
+        self.suspension = Some(Suspension {
+            ungrab: suspend.ungrab,
+            resume_at: suspend.timeout.map(|timeout| self.clock.now() + timeout),
+            pressed_keys: HashSet::new(),
+        });
+        println!("Suspended remapping");
+        Ok(())
+    }
+
+    // Forget the keys held on input devices, whose releases may never come, e.g. from a removed device.
+    // Keys pressed by remapping are left to ActionDispatcher#release_all.
+    pub fn release_all(&mut self) -> Result<(), Box<dyn Error>> {
+        self.remove_override()?;
+        self.modifiers.clear();
+        self.extra_modifiers.clear();
+        self.pressed_keys.clear();
+        self.multi_purpose_keys.clear();
+        Ok(())
+    }
+
+    pub fn resume(&mut self) {
+        if self.suspension.take().is_some() {
+            println!("Resumed remapping");
+        }
+    }
+
+    pub fn suspend_state(&self) -> SuspendState {
+        match &self.suspension {
+            None => SuspendState::Active,
+            Some(Suspension { ungrab: false, .. }) => SuspendState::PassThrough,
+            Some(Suspension { ungrab: true, .. }) => SuspendState::Ungrabbed,
+        }
+    }
+
+    // Handle an Event and return Actions. This is the main interface of EventHandler.
     pub fn on_events(&mut self, events: &Vec<Event>, config: &Config) -> Result<Vec<Action>, Box<dyn Error>> {
         // a vector to collect mouse movement events to be able to send them all at once as one MouseMovementEventCollection.
         let mut mouse_movement_collection: Vec<RelativeEvent> = Vec::new();
         for event in events {
+            if self.suspension.is_some() {
+                self.on_suspended_event(event, &mut mouse_movement_collection, config);
+                continue;
+            }
             match event {
                 Event::KeyEvent(device, key_event) => {
                     self.on_key_event(key_event, config, device)?;
@@ -107,6 +332,69 @@ impl EventHandler {
         Ok(self.actions.drain(..).collect())
     }
 
+    // Send events as is while suspended, except for a key press bound to { resume: true }
+    fn on_suspended_event(
+        &mut self,
+        event: &Event,
+        mouse_movement_collection: &mut Vec<RelativeEvent>,
+        config: &Config,
+    ) {
+        let suspension = self.suspension.as_mut().expect("suspended");
+        let ungrab = suspension.ungrab;
+        match event {
+            Event::KeyEvent(_, key_event) => {
+                let key = key_event.key;
+                let value = key_event.value();
+                if value == PRESS {
+                    suspension.pressed_keys.insert(key);
+                } else if value == RELEASE {
+                    suspension.pressed_keys.remove(&key);
+                }
+                let timed_out = suspension.timed_out(self.clock.now());
+                // Keep modifiers in sync so that the state is right on resume
+                if MODIFIER_KEYS.contains(&key) {
+                    self.update_modifier(key, value);
+                }
+                if value == PRESS && self.is_resume_key(config, &key) {
+                    self.resume();
+                    return;
+                }
+                if !ungrab {
+                    self.send_key(&key, value);
+                }
+                if timed_out {
+                    self.resume();
+                }
+            }
+            Event::RelativeEvent(_, relative_event) if !ungrab => {
+                let action = RelativeEvent::new_with(relative_event.code, relative_event.value);
+                if relative_event.code <= 2 {
+                    mouse_movement_collection.push(action);
+                } else {
+                    self.send_action(Action::RelativeEvent(action));
+                }
+            }
+            Event::OtherEvents(event) if !ungrab => self.send_action(Action::InputEvent(*event)),
+            // Otherwise the release of the last held key resumes it
+            Event::OverrideTimeout if suspension.timed_out(self.clock.now()) => self.resume(),
+            _ => {}
+        }
+    }
+
+    // Whether the key is bound to { resume: true } with the current modifiers.
+    // application and window are ignored so that it never fails to resume.
+    fn is_resume_key(&self, config: &Config, key: &Key) -> bool {
+        config.keymap_table.get(key).is_some_and(|entries| {
+            entries.iter().any(|entry| {
+                entry
+                    .actions
+                    .iter()
+                    .any(|action| matches!(action, KeymapAction::Resume))
+                    && self.diff_modifiers(&entry.modifiers).1.is_empty()
+            })
+        })
+    }
+
     // Handle EventType::KEY
     fn on_key_event(
         &mut self,
@@ -116,8 +404,12 @@ impl EventHandler {
     ) -> Result<bool, Box<dyn Error>> {
         self.application_cache = None; // expire cache
         self.title_cache = None; // expire cache
+        self.properties_cache = None; // expire cache
         let key = Key::new(event.code());
         debug!("=> {}: {:?}", event.value(), &key);
+        if is_pressed(event.value()) && (!config.application_modes.is_empty() || config.mode_memory.is_some()) {
+            self.update_focus(config);
+        }
 
         // Apply modmap
         let mut key_values = if let Some(key_action) = self.find_modmap(config, &key, device) {
@@ -179,35 +471,7 @@ impl EventHandler {
         const RELEASE: i32 = 0;
         const PRESS: i32 = 1;
 
-        // All relative events (except maybe those i haven't found information about (REL_DIAL, REL_MISC and REL_RESERVED))
-        // can have either a positive value or a negative value.
-        // A negative value is associated with a different action than the positive value.
-        // Specifically, negative values are associated with the opposite of the action that would emit a positive value.
-        // For example, a positive value for a scroll event (REL_WHEEL) comes from an upscroll, while a negative value comes from a downscroll.
-        let key = match event.value {
-            // Positive and negative values can be really high because the events are relative,
-            // so their values are variable, meaning we have to match with all positive/negative values.
-            // Not sure if there is any relative event with a fixed value.
-            1..=i32::MAX => (event.code * 2) + DISGUISED_EVENT_OFFSETTER,
-            // While some events may appear to have a fixed value,
-            // events like scrolling will have higher values with more "agressive" scrolling.
-
-            // *2 to create a "gap" between events (since multiplying by two means that all resulting values will be even, the odd numbers between will be missing),
-            // +1 if the event has a negative value to "fill" the gap (since adding one shifts the parity from even to odd),
-            // and adding DISGUISED_EVENT_OFFSETTER,
-            // so that the total as a keycode corresponds to one of the custom aliases that
-            // are created in config::key::parse_key specifically for these "disguised" relative events.
-            i32::MIN..=-1 => (event.code * 2) + 1 + DISGUISED_EVENT_OFFSETTER,
-
-            0 => {
-                println!("This event has a value of zero : {:?}", event);
-                // A value of zero would be unexpected for a relative event,
-                // since changing something by zero is kinda useless.
-                // Just in case it can actually happen (and also because match arms need the same output type),
-                // we'll just act like the value of the event was a positive.
-                (event.code * 2) + DISGUISED_EVENT_OFFSETTER
-            }
-        };
+        let key = disguised_key(event).code();
 
         // Sending a RELATIVE event "disguised" as a "fake" KEY event press to on_key_event.
         match self.on_key_event(&KeyEvent::new_with(key, PRESS), config, &device)? {
@@ -253,7 +517,10 @@ impl EventHandler {
     }
 
     fn remove_override(&mut self) -> Result<(), Box<dyn Error>> {
-        self.override_timer.unset()?;
+        // There are no nested remaps while suspended, when the timer is for the timeout of suspend
+        if self.suspension.is_none() {
+            self.timer.unset()?;
+        }
         self.override_remaps.clear();
         self.override_timeout_key = None;
         Ok(())
@@ -314,17 +581,17 @@ impl EventHandler {
                         MultiPurposeKeyState {
                             held,
                             alone,
-                            alone_timeout_at: Some(Instant::now() + alone_timeout),
+                            alone_timeout_at: Some(self.clock.now() + alone_timeout),
                         },
                     );
                     return Ok(vec![]); // delay the press
                 } else if value == REPEAT {
                     if let Some(state) = self.multi_purpose_keys.get_mut(&key) {
-                        return Ok(state.repeat());
+                        return Ok(state.repeat(self.clock.now()));
                     }
                 } else if value == RELEASE {
                     if let Some(state) = self.multi_purpose_keys.remove(&key) {
-                        return Ok(state.release());
+                        return Ok(state.release(self.clock.now()));
                     }
                 } else {
                     panic!("unexpected key event value: {}", value);
@@ -388,21 +655,12 @@ impl EventHandler {
     fn find_modmap(&mut self, config: &Config, key: &Key, device: &InputDeviceInfo) -> Option<ModmapAction> {
         for modmap in &config.modmap {
             if let Some(key_action) = modmap.remap.get(key) {
-                if let Some(window_matcher) = &modmap.window {
-                    if !self.match_window(window_matcher) {
-                        continue;
-                    }
-                }
-                if let Some(application_matcher) = &modmap.application {
-                    if !self.match_application(application_matcher) {
-                        continue;
-                    }
-                }
-                if let Some(device_matcher) = &modmap.device {
-                    if !self.match_device(device_matcher, device) {
+                if let Some(condition) = &modmap.condition {
+                    if !self.match_condition(condition, device) {
                         continue;
                     }
                 }
+                self.record_match("modmap", &modmap.name);
                 return Some(key_action.clone());
             }
         }
@@ -441,8 +699,10 @@ impl EventHandler {
 
                         // If the first/top match was a remap, continue to find rest of the eligible remaps for this key
                         if remaps.is_empty() && !is_remap {
+                            self.record_match("nested remap", "");
                             return Ok(Some(actions));
                         } else if is_remap {
+                            self.record_match("nested remap", "");
                             remaps.extend(actions);
                         }
                     }
@@ -466,24 +726,8 @@ impl EventHandler {
                     if (exact_match && extra_modifiers.len() > 0) || missing_modifiers.len() > 0 {
                         continue;
                     }
-                    if let Some(window_matcher) = &entry.title {
-                        if !self.match_window(window_matcher) {
-                            continue;
-                        }
-                    }
-
-                    if let Some(application_matcher) = &entry.application {
-                        if !self.match_application(application_matcher) {
-                            continue;
-                        }
-                    }
-                    if let Some(device_matcher) = &entry.device {
-                        if !self.match_device(device_matcher, device) {
-                            continue;
-                        }
-                    }
-                    if let Some(modes) = &entry.mode {
-                        if !modes.contains(&self.mode) {
+                    if let Some(condition) = &entry.condition {
+                        if !self.match_condition(condition, device) {
                             continue;
                         }
                     }
@@ -493,8 +737,10 @@ impl EventHandler {
 
                     // If the first/top match was a remap, continue to find rest of the eligible remaps for this key
                     if remaps.is_empty() && !is_remap {
+                        self.record_match("keymap", &entry.name);
                         return Ok(Some(actions));
                     } else if is_remap {
+                        self.record_match("keymap", &entry.name);
                         remaps.extend(actions)
                     }
                 }
@@ -506,6 +752,16 @@ impl EventHandler {
         Ok(None)
     }
 
+    fn record_match(&mut self, kind: &str, name: &str) {
+        if let Some(entries) = &mut self.matched_entries {
+            entries.push(if name.is_empty() {
+                kind.to_string()
+            } else {
+                format!("{kind} '{name}'")
+            });
+        }
+    }
+
     fn dispatch_actions(&mut self, actions: &Vec<TaggedAction>, key: &Key) -> Result<(), Box<dyn Error>> {
         for action in actions {
             self.dispatch_action(action, key)?;
@@ -529,10 +785,9 @@ impl EventHandler {
                 // so the behaviour is consistent with how current normal keymap override works
                 if set_timeout {
                     if let Some(timeout) = timeout {
-                        let expiration = Expiration::OneShot(TimeSpec::from_duration(*timeout));
                         // TODO: Consider handling the timer in ActionDispatcher
-                        self.override_timer.unset()?;
-                        self.override_timer.set(expiration, TimerSetTimeFlags::empty())?;
+                        self.timer.unset()?;
+                        self.timer.set(*timeout)?;
                         self.override_timeout_key = timeout_key.or_else(|| Some(*key));
                     }
                 }
@@ -545,6 +800,8 @@ impl EventHandler {
             KeymapAction::SetMark(set) => self.mark_set = *set,
             KeymapAction::WithMark(key_press) => self.send_key_press(&self.with_mark(key_press)),
             KeymapAction::EscapeNextKey(escape_next_key) => self.escape_next_key = *escape_next_key,
+            KeymapAction::Suspend(suspend) => self.suspend(suspend)?,
+            KeymapAction::Resume => self.resume(),
             KeymapAction::SetExtraModifiers(keys) => {
                 self.extra_modifiers.clear();
                 for key in keys {
@@ -637,54 +894,115 @@ impl EventHandler {
             Modifier::Key(key) => self.modifiers.contains(key),
         }
     }
-    fn match_window(&mut self, window_matcher: &OnlyOrNot) -> bool {
-        // Lazily fill the wm_class cache
-        if self.title_cache.is_none() {
-            match self.application_client.current_window() {
-                Some(title) => self.title_cache = Some(title),
-                None => self.title_cache = Some(String::new()),
+    fn match_condition(&mut self, condition: &Condition, device: &InputDeviceInfo) -> bool {
+        match condition {
+            Condition::All(conditions) => conditions.iter().all(|c| self.match_condition(c, device)),
+            Condition::Any(conditions) => conditions.iter().any(|c| self.match_condition(c, device)),
+            Condition::Not(condition) => !self.match_condition(condition, device),
+            Condition::Application(matchers) => matches_any(matchers, self.current_application()),
+            Condition::Window(matchers) => matches_any(matchers, self.current_window()),
+            // A property the client doesn't know matches no matcher
+            Condition::Executable(matchers) => {
+                self.window_properties()
+                    .executable
+                    .as_deref()
+                    .is_some_and(|executable| {
+                        // Match either the path or the file name
+                        let file_name = Path::new(executable).file_name().and_then(|name| name.to_str());
+                        matches_any(matchers, executable) || file_name.is_some_and(|name| matches_any(matchers, name))
+                    })
             }
-        }
-
-        if let Some(title) = &self.title_cache {
-            if let Some(title_only) = &window_matcher.only {
-                return title_only.iter().any(|m| m.matches(title));
+            Condition::Workspace(matchers) => matches_known(matchers, self.window_properties().workspace.as_deref()),
+            Condition::Output(matchers) => matches_known(matchers, self.window_properties().output.as_deref()),
+            Condition::ResourceName(matchers) => {
+                matches_known(matchers, self.window_properties().resource_name.as_deref())
             }
-            if let Some(title_not) = &window_matcher.not {
-                return title_not.iter().all(|m| !m.matches(title));
+            Condition::ResourceClass(matchers) => {
+                matches_known(matchers, self.window_properties().resource_class.as_deref())
             }
+            // A property the client doesn't know matches neither true nor false
+            Condition::Floating(floating) => self.window_properties().floating == Some(*floating),
+            Condition::Fullscreen(fullscreen) => self.window_properties().fullscreen == Some(*fullscreen),
+            Condition::Xwayland(xwayland) => self.window_properties().xwayland == Some(*xwayland),
+            Condition::Device(filters) => filters.iter().any(|filter| device.matches(filter)),
+            Condition::Mode(modes) => modes.contains(&self.mode),
+            Condition::Pressed(keys) => keys
+                .iter()
+                .all(|key| self.pressed_keys.contains_key(key) || self.modifiers.contains(key)),
+            Condition::Env(variables) => variables
+                .iter()
+                .all(|(name, value)| env::var(name).is_ok_and(|var| &var == value)),
         }
-        false
     }
 
-    fn match_application(&mut self, application_matcher: &OnlyOrNot) -> bool {
+    fn window_properties(&mut self) -> &WindowProperties {
+        // Lazily fill the window properties cache
+        self.properties_cache
+            .get_or_insert_with(|| self.application_client.window_properties())
+    }
+
+    fn current_window(&mut self) -> &str {
+        // Lazily fill the title cache
+        self.title_cache
+            .get_or_insert_with(|| self.application_client.current_window().unwrap_or_default())
+    }
+
+    fn current_application(&mut self) -> &str {
         // Lazily fill the wm_class cache
-        if self.application_cache.is_none() {
-            match self.application_client.current_application() {
-                Some(application) => self.application_cache = Some(application),
-                None => self.application_cache = Some(String::new()),
-            }
-        }
+        self.application_cache
+            .get_or_insert_with(|| self.application_client.current_application().unwrap_or_default())
+    }
 
-        if let Some(application) = &self.application_cache {
-            if let Some(application_only) = &application_matcher.only {
-                return application_only.iter().any(|m| m.matches(application));
-            }
-            if let Some(application_not) = &application_matcher.not {
-                return application_not.iter().all(|m| !m.matches(application));
-            }
+    // Apply application_modes and mode_memory when the focus has changed
+    fn update_focus(&mut self, config: &Config) {
+        let application = self.current_application().to_string();
+        let focus = match config.mode_memory {
+            Some(ModeMemory::Window) => format!("{}: {}", application, self.current_window()),
+            _ => application.clone(),
+        };
+        if self.focus.as_ref() == Some(&focus) {
+            return;
         }
-        false
+        let previous_mode = self.mode.clone();
+        if config.mode_memory.is_some() {
+            self.switch_mode_memory(config, &application, &focus);
+        } else {
+            self.switch_application_mode(config, &application);
+        }
+        if self.mode != previous_mode {
+            println!("mode: {}", self.mode);
+        }
+        self.focus = Some(focus);
     }
 
-    fn match_device(&self, device_matcher: &config::device::Device, device: &InputDeviceInfo) -> bool {
-        if let Some(device_only) = &device_matcher.only {
-            return device_only.iter().any(|m| device.matches(m));
+    // Switch to the mode of the first application_modes entry matching a newly focused application,
+    // and restore the previous mode when the focus leaves it.
+    fn switch_application_mode(&mut self, config: &Config, application: &str) {
+        if let Some(mode) = self.mode_before_application.take() {
+            self.mode = mode;
         }
-        if let Some(device_not) = &device_matcher.not {
-            return device_not.iter().all(|m| !device.matches(m));
+        if let Some(application_mode) = config.application_modes.iter().find(|m| m.matches(application)) {
+            self.mode_before_application = Some(std::mem::replace(&mut self.mode, application_mode.mode.clone()));
         }
-        false
+    }
+
+    // Remember the state of the previous focus, and restore the one of the new focus.
+    // A focus seen for the first time starts from application_modes or default_mode.
+    fn switch_mode_memory(&mut self, config: &Config, application: &str, focus: &str) {
+        if let Some(previous_focus) = self.focus.take() {
+            self.mode_memories
+                .remember(previous_focus, (self.mode.clone(), self.mark_set));
+        }
+        (self.mode, self.mark_set) = match self.mode_memories.recall(focus) {
+            Some(state) => state,
+            None => {
+                let mode = match config.application_modes.iter().find(|m| m.matches(application)) {
+                    Some(application_mode) => application_mode.mode.clone(),
+                    None => config.default_mode.clone(),
+                };
+                (mode, false)
+            }
+        };
     }
 
     fn update_modifier(&mut self, key: Key, value: i32) {
@@ -696,6 +1014,56 @@ impl EventHandler {
     }
 }
 
+// The key that a relative event is disguised as, so that modmap and keymap can handle it
+// with the custom aliases created in config::key::parse_key.
+pub fn disguised_key(event: &RelativeEvent) -> Key {
+    // All relative events (except maybe those i haven't found information about (REL_DIAL, REL_MISC and REL_RESERVED))
+    // can have either a positive value or a negative value.
+    // A negative value is associated with a different action than the positive value.
+    // Specifically, negative values are associated with the opposite of the action that would emit a positive value.
+    // For example, a positive value for a scroll event (REL_WHEEL) comes from an upscroll, while a negative value comes from a downscroll.
+    let code = match event.value {
+        // Positive and negative values can be really high because the events are relative,
+        // so their values are variable, meaning we have to match with all positive/negative values.
+        // Not sure if there is any relative event with a fixed value.
+        1..=i32::MAX => (event.code * 2) + DISGUISED_EVENT_OFFSETTER,
+        // While some events may appear to have a fixed value,
+        // events like scrolling will have higher values with more "agressive" scrolling.
+
+        // *2 to create a "gap" between events (since multiplying by two means that all resulting values will be even, the odd numbers between will be missing),
+        // +1 if the event has a negative value to "fill" the gap (since adding one shifts the parity from even to odd),
+        // and adding DISGUISED_EVENT_OFFSETTER,
+        // so that the total as a keycode corresponds to one of the custom aliases that
+        // are created in config::key::parse_key specifically for these "disguised" relative events.
+        i32::MIN..=-1 => (event.code * 2) + 1 + DISGUISED_EVENT_OFFSETTER,
+
+        0 => {
+            println!("This event has a value of zero : {:?}", event);
+            // A value of zero would be unexpected for a relative event,
+            // since changing something by zero is kinda useless.
+            // Just in case it can actually happen (and also because match arms need the same output type),
+            // we'll just act like the value of the event was a positive.
+            (event.code * 2) + DISGUISED_EVENT_OFFSETTER
+        }
+    };
+    Key(code)
+}
+
+fn matches_any(matchers: &[ApplicationMatcher], value: &str) -> bool {
+    matchers.iter().any(|matcher| matcher.matches(value))
+}
+
+fn matches_known(matchers: &[ApplicationMatcher], value: Option<&str>) -> bool {
+    value.is_some_and(|value| matches_any(matchers, value))
+}
+
+fn is_multi_purpose_key(config: &Config, key: &Key) -> bool {
+    config
+        .modmap
+        .iter()
+        .any(|modmap| matches!(modmap.remap.get(key), Some(ModmapAction::MultiPurposeKey(..))))
+}
+
 fn is_remap(actions: &Vec<KeymapAction>) -> bool {
     actions.iter().all(|x| match x {
         KeymapAction::Remap(..) => true,
@@ -784,9 +1152,9 @@ struct MultiPurposeKeyState {
 }
 
 impl MultiPurposeKeyState {
-    fn repeat(&mut self) -> Vec<(Key, i32)> {
+    fn repeat(&mut self, now: Instant) -> Vec<(Key, i32)> {
         if let Some(alone_timeout_at) = &self.alone_timeout_at {
-            if Instant::now() < *alone_timeout_at {
+            if now < *alone_timeout_at {
                 vec![] // still delay the press
             } else {
                 self.alone_timeout_at = None; // timeout
@@ -801,9 +1169,9 @@ impl MultiPurposeKeyState {
         }
     }
 
-    fn release(&self) -> Vec<(Key, i32)> {
+    fn release(&self, now: Instant) -> Vec<(Key, i32)> {
         if let Some(alone_timeout_at) = &self.alone_timeout_at {
-            if Instant::now() < *alone_timeout_at {
+            if now < *alone_timeout_at {
                 // dispatch the delayed press and this release
                 let mut release_keys = self.alone.clone().into_vec();
                 release_keys.sort_by(modifiers_last);
@@ -0,0 +1,1317 @@
+use crate::action::Action;
+use crate::action_dispatcher::ActionDispatcher;
+use crate::chord::Chords;
+use crate::clock::{Clock, SystemClock};
+use crate::config::check::check_config;
+use crate::config::device::DeviceMatcher;
+use crate::config::escape_chord::EscapeChordAction;
+use crate::config::suspend::Suspend;
+use crate::config::{load_configs, Config};
+use crate::device::{
+    output_device, select_devices, DeviceSelection, InputDevice, InputSource, OutputDevice, LINK_DIRS,
+};
+use crate::event::Event;
+use crate::event_handler::{EventHandler, SuspendState};
+use crate::recorder::Recorder;
+use crate::select_readable;
+use anyhow::{anyhow, bail, Context};
+use evdev::{BusType, EventType};
+use nix::libc::ENODEV;
+use nix::sys::inotify::{AddWatchFlags, Inotify, InotifyEvent};
+use nix::sys::signal::Signal;
+use nix::sys::signalfd::SignalFd;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+// InputEvent#value
+const PRESS: i32 = 1;
+
+// What the event loop needs from the system: devices, select(2), inotify, signals and the clock.
+// SystemPlatform is the real one, and tests run the loop on a fake one.
+pub trait Platform {
+    type Device: InputSource;
+    // All devices under /dev/input
+    fn input_devices(&mut self) -> io::Result<Vec<Self::Device>>;
+    // A device that has appeared under /dev/input
+    fn open_device(&mut self, path: PathBuf) -> io::Result<Self::Device>;
+    fn output_device(
+        &mut self,
+        bus_type: Option<BusType>,
+        mouse: bool,
+        output: Option<&str>,
+    ) -> Result<Box<dyn OutputDevice>, Box<dyn Error>>;
+    // Block until the devices, the timer of EventHandler, the watchers or signals have something
+    fn wait(&mut self, devices: &HashMap<PathBuf, Self::Device>) -> anyhow::Result<Readiness>;
+    // Events of --watch=device and --watch=config since the last call. Empty if they aren't watched.
+    fn device_changes(&mut self) -> Vec<WatchEvent>;
+    fn config_changes(&mut self) -> Vec<WatchEvent>;
+    // The clock of EventHandler and ActionDispatcher
+    fn clock(&self) -> Box<dyn Clock>;
+}
+
+// What Platform#wait has found
+#[derive(Debug, Default)]
+pub struct Readiness {
+    // The timer of nested remaps has expired
+    pub timer: bool,
+    // Devices with events to fetch
+    pub devices: Vec<PathBuf>,
+    pub signal: Option<Signal>,
+}
+
+// An inotify event of the device or config watcher
+#[derive(Clone, Debug)]
+pub struct WatchEvent {
+    pub mask: AddWatchFlags,
+    pub name: Option<OsString>,
+}
+
+impl From<InotifyEvent> for WatchEvent {
+    fn from(event: InotifyEvent) -> Self {
+        WatchEvent {
+            mask: event.mask,
+            name: event.name,
+        }
+    }
+}
+
+pub struct SystemPlatform {
+    timer_fd: RawFd,
+    signal_fd: SignalFd,
+    device_watcher: Option<Inotify>,
+    config_watcher: Option<Inotify>,
+}
+
+impl SystemPlatform {
+    pub fn new(
+        timer_fd: RawFd,
+        signal_fd: SignalFd,
+        device_watcher: Option<Inotify>,
+        config_watcher: Option<Inotify>,
+    ) -> SystemPlatform {
+        SystemPlatform {
+            timer_fd,
+            signal_fd,
+            device_watcher,
+            config_watcher,
+        }
+    }
+}
+
+impl Platform for SystemPlatform {
+    type Device = InputDevice;
+
+    fn input_devices(&mut self) -> io::Result<Vec<InputDevice>> {
+        Ok(InputDevice::devices()?.collect())
+    }
+
+    fn open_device(&mut self, path: PathBuf) -> io::Result<InputDevice> {
+        InputDevice::try_from(path)
+    }
+
+    fn output_device(
+        &mut self,
+        bus_type: Option<BusType>,
+        mouse: bool,
+        output: Option<&str>,
+    ) -> Result<Box<dyn OutputDevice>, Box<dyn Error>> {
+        Ok(Box::new(output_device(bus_type, mouse, output)?))
+    }
+
+    fn wait(&mut self, devices: &HashMap<PathBuf, InputDevice>) -> anyhow::Result<Readiness> {
+        let watchers: Vec<_> = self.device_watcher.iter().chain(self.config_watcher.iter()).collect();
+        let signal_fd = self.signal_fd.as_raw_fd();
+        let readable_fds = select_readable(devices.values(), &watchers, &[self.timer_fd, signal_fd])?;
+        let signal = match readable_fds.contains(signal_fd) {
+            true => self.signal_fd.read_signal()?,
+            false => None,
+        };
+        Ok(Readiness {
+            timer: readable_fds.contains(self.timer_fd),
+            devices: devices
+                .iter()
+                .filter(|(_, device)| readable_fds.contains(device.as_raw_fd()))
+                .map(|(path, _)| path.clone())
+                .collect(),
+            signal: signal
+                .map(|signal| Signal::try_from(signal.ssi_signo as i32))
+                .transpose()?,
+        })
+    }
+
+    fn device_changes(&mut self) -> Vec<WatchEvent> {
+        read_watcher(&self.device_watcher)
+            .into_iter()
+            .map(link_target)
+            .collect()
+    }
+
+    fn config_changes(&mut self) -> Vec<WatchEvent> {
+        read_watcher(&self.config_watcher)
+    }
+
+    fn clock(&self) -> Box<dyn Clock> {
+        Box::new(SystemClock)
+    }
+}
+
+// A new link in /dev/input/by-id or by-path stands for the device it points to
+fn link_target(event: WatchEvent) -> WatchEvent {
+    let target = event.name.as_ref().and_then(|name| {
+        LINK_DIRS
+            .iter()
+            .find_map(|dir| Path::new(dir).join(name).canonicalize().ok())
+    });
+    match target.as_ref().and_then(|target| target.file_name()) {
+        Some(name) => WatchEvent {
+            name: Some(name.to_owned()),
+            ..event
+        },
+        None => event,
+    }
+}
+
+fn read_watcher(watcher: &Option<Inotify>) -> Vec<WatchEvent> {
+    match watcher.map(|inotify| inotify.read_events()) {
+        Some(Ok(events)) => events.into_iter().map(WatchEvent::from).collect(),
+        _ => vec![],
+    }
+}
+
+// --device, --ignore, --mouse, and whether --watch=device is given
+pub struct DeviceArgs {
+    pub device: Vec<DeviceMatcher>,
+    pub ignore: Vec<DeviceMatcher>,
+    pub mouse: Option<bool>,
+    pub watch: bool,
+}
+
+// TODO: Unify this with Event
+enum ReloadEvent {
+    // force: reload even if the config files don't seem modified
+    ReloadConfig { force: bool },
+    ReloadDevices,
+    EscapeChord,
+}
+
+// What handle_input_events found other than events to remap
+enum InputStatus {
+    Handled,
+    DeviceRemoved,
+    EscapeChord,
+}
+
+// Whether escape_chord has paused remapping
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PauseState {
+    Running,
+    Paused,
+    // The chord is held again. Grab devices once it's released, not to take the releases from other clients.
+    Resuming,
+}
+
+// The main loop of xremap: Remap events of the selected devices, and follow devices, config files and signals
+pub struct EventLoop<P: Platform> {
+    platform: P,
+    config: Config,
+    config_paths: Vec<PathBuf>,
+    args: DeviceArgs,
+    selection: DeviceSelection,
+    input_devices: HashMap<PathBuf, P::Device>,
+    // The bus type of output devices, which is taken from the first input device
+    bus_type: Option<BusType>,
+    handler: EventHandler,
+    dispatcher: ActionDispatcher,
+    // devices.options.output of the device that has pressed a key last, which the timeout of nested remaps goes to
+    timer_output: Option<String>,
+    recorder: Option<Recorder>,
+    chords: Chords,
+    pause_state: PauseState,
+    suspend_state: SuspendState,
+}
+
+impl<P: Platform> EventLoop<P> {
+    pub fn new(
+        mut platform: P,
+        config: Config,
+        config_paths: Vec<PathBuf>,
+        args: DeviceArgs,
+        handler: EventHandler,
+        recorder: Option<Recorder>,
+    ) -> anyhow::Result<EventLoop<P>> {
+        let selection = DeviceSelection::new(&config.devices, &args.device, &args.ignore, args.mouse);
+        let input_devices = get_input_devices(&mut platform, &selection, args.watch, true)?;
+        let bus_type = input_devices.values().next().map(InputSource::bus_type);
+        let output_device = match platform.output_device(bus_type, selection.mouse, None) {
+            Ok(output_device) => output_device,
+            Err(e) => bail!("Failed to prepare an output device: {}", e),
+        };
+        let mut dispatcher = ActionDispatcher::new(output_device, platform.clock());
+        add_outputs(&mut platform, &mut dispatcher, &selection, bus_type)?;
+        let chords = Chords::new(&config);
+        Ok(EventLoop {
+            platform,
+            config,
+            config_paths,
+            args,
+            selection,
+            input_devices,
+            bus_type,
+            handler,
+            dispatcher,
+            timer_output: None,
+            recorder,
+            chords,
+            pause_state: PauseState::Running,
+            suspend_state: SuspendState::Active,
+        })
+    }
+
+    // Run until SIGTERM, SIGINT or escape_chord of `action: exit`
+    pub fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            match 'event_loop: loop {
+                self.suspend_state = sync_suspend_state(
+                    self.suspend_state,
+                    &self.handler,
+                    &mut self.dispatcher,
+                    &mut self.input_devices,
+                    self.pause_state == PauseState::Running,
+                )?;
+                let readiness = self.platform.wait(&self.input_devices)?;
+                if readiness.timer {
+                    self.dispatcher.set_output(self.timer_output.as_deref());
+                    if let Err(error) = handle_events(
+                        &mut self.handler,
+                        &mut self.dispatcher,
+                        &mut self.config,
+                        &mut self.recorder,
+                        vec![Event::OverrideTimeout],
+                    ) {
+                        println!("Error on remap timeout: {error}")
+                    }
+                    self.dispatcher.set_output(None);
+                }
+
+                for (path, input_device) in self.input_devices.iter_mut() {
+                    if !readiness.devices.contains(path) {
+                        continue;
+                    }
+
+                    match handle_input_events(
+                        input_device,
+                        &mut self.handler,
+                        &mut self.dispatcher,
+                        &mut self.config,
+                        &mut self.recorder,
+                        &mut self.chords,
+                        &mut self.timer_output,
+                        self.pause_state != PauseState::Running,
+                    )? {
+                        InputStatus::Handled => {}
+                        InputStatus::DeviceRemoved => {
+                            println!("Found a removed device. Reselecting devices.");
+                            break 'event_loop ReloadEvent::ReloadDevices;
+                        }
+                        InputStatus::EscapeChord => break 'event_loop ReloadEvent::EscapeChord,
+                    }
+                }
+                if self.pause_state == PauseState::Resuming && self.chords.escape.is_released() {
+                    println!("Resuming remapping.");
+                    // EventHandler has seen no keys while paused
+                    release_all(&mut self.handler, &mut self.dispatcher).context("Releasing keys on resume")?;
+                    if self.suspend_state != SuspendState::Ungrabbed {
+                        for input_device in self.input_devices.values_mut() {
+                            input_device.grab();
+                        }
+                    }
+                    self.pause_state = PauseState::Running;
+                }
+
+                let grab = self.grabs_devices();
+                handle_device_changes(
+                    self.platform.device_changes(),
+                    &mut self.platform,
+                    &mut self.input_devices,
+                    &self.selection,
+                    grab,
+                )?;
+                if !handle_config_changes(
+                    self.platform.config_changes(),
+                    &mut self.platform,
+                    &mut self.input_devices,
+                    &self.selection,
+                    &self.config_paths,
+                )? {
+                    break 'event_loop ReloadEvent::ReloadConfig { force: false };
+                }
+                if let Some(signal) = readiness.signal {
+                    println!("Received {signal}.");
+                    match signal {
+                        Signal::SIGHUP => break 'event_loop ReloadEvent::ReloadConfig { force: true },
+                        Signal::SIGUSR1 => self
+                            .handler
+                            .suspend(&Suspend::default())
+                            .map_err(|e| anyhow!("Failed suspending remapping: {e:?}"))?,
+                        Signal::SIGUSR2 => self.handler.resume(),
+                        // SIGTERM or SIGINT
+                        _ => return shutdown(&mut self.dispatcher, &mut self.input_devices),
+                    }
+                }
+            } {
+                ReloadEvent::ReloadDevices => {
+                    let grab = self.grabs_devices();
+                    reload_devices(
+                        &mut self.platform,
+                        &mut self.handler,
+                        &mut self.dispatcher,
+                        &mut self.input_devices,
+                        &self.selection,
+                        self.args.watch,
+                        grab,
+                    )?;
+                }
+                ReloadEvent::EscapeChord => match (self.pause_state, self.config.escape_chord.action) {
+                    (PauseState::Running, EscapeChordAction::Exit) => {
+                        println!("Escape chord is held. Exiting.");
+                        return shutdown(&mut self.dispatcher, &mut self.input_devices);
+                    }
+                    (PauseState::Running, EscapeChordAction::Pause) => {
+                        println!("Escape chord is held. Pausing remapping until it's held again.");
+                        release_all(&mut self.handler, &mut self.dispatcher).context("Releasing keys on pause")?;
+                        for input_device in self.input_devices.values_mut() {
+                            input_device.ungrab();
+                        }
+                        self.pause_state = PauseState::Paused;
+                    }
+                    (PauseState::Paused, _) => self.pause_state = PauseState::Resuming,
+                    (PauseState::Resuming, _) => {}
+                },
+                ReloadEvent::ReloadConfig { force } => self.reload_config(force)?,
+            }
+        }
+    }
+
+    // Whether devices should be grabbed now
+    fn grabs_devices(&self) -> bool {
+        self.pause_state == PauseState::Running && self.suspend_state != SuspendState::Ungrabbed
+    }
+
+    fn reload_config(&mut self, force: bool) -> anyhow::Result<()> {
+        let current_mtime = self
+            .config_paths
+            .iter()
+            .filter_map(|p| p.metadata().ok()?.modified().ok())
+            .max();
+        if let (Some(last_mtime), Some(current_mtime)) = (self.config.modify_time, current_mtime) {
+            if last_mtime == current_mtime && !force {
+                return Ok(());
+            }
+        }
+        let config = match load_configs(&self.config_paths) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Failed to reload config, keeping the previous one: {e}");
+                return Ok(());
+            }
+        };
+        println!("Reloading Config");
+        for warning in check_config(&config) {
+            println!("warning: {warning}");
+        }
+        self.config = config;
+        self.chords = Chords::new(&self.config);
+        let selection =
+            DeviceSelection::new(&self.config.devices, &self.args.device, &self.args.ignore, self.args.mouse);
+        if selection != self.selection {
+            println!("Devices of the config have changed. Reselecting devices.");
+            self.selection = selection;
+            add_outputs(&mut self.platform, &mut self.dispatcher, &self.selection, self.bus_type)?;
+            let grab = self.grabs_devices();
+            reload_devices(
+                &mut self.platform,
+                &mut self.handler,
+                &mut self.dispatcher,
+                &mut self.input_devices,
+                &self.selection,
+                self.args.watch,
+                grab,
+            )?;
+            self.dispatcher.retain_outputs(&self.selection.outputs())?;
+        }
+        let actions = self
+            .handler
+            .on_config_reload(&self.config)
+            .map_err(|e| anyhow!("Failed migrating to the reloaded config: {e:?}"))?;
+        dispatch_actions(&mut self.dispatcher, &mut self.recorder, actions)
+    }
+}
+
+fn get_input_devices<P: Platform>(
+    platform: &mut P,
+    selection: &DeviceSelection,
+    watch: bool,
+    grab: bool,
+) -> anyhow::Result<HashMap<PathBuf, P::Device>> {
+    let input_devices = platform
+        .input_devices()
+        .map_err(anyhow::Error::from)
+        .and_then(|devices| select_devices(devices, selection, watch, grab));
+    match input_devices {
+        Ok(input_devices) => Ok(input_devices),
+        Err(e) => bail!("Failed to prepare input devices: {}", e),
+    }
+}
+
+// Create output devices for devices.options.output that don't exist yet
+fn add_outputs<P: Platform>(
+    platform: &mut P,
+    dispatcher: &mut ActionDispatcher,
+    selection: &DeviceSelection,
+    bus_type: Option<BusType>,
+) -> anyhow::Result<()> {
+    for output in selection.outputs() {
+        if !dispatcher.has_output(output) {
+            let device = platform
+                .output_device(bus_type, selection.mouse, Some(output))
+                .map_err(|e| anyhow!("Failed to prepare an output device '{output}': {e}"))?;
+            dispatcher.add_output(output.to_string(), device);
+        }
+    }
+    Ok(())
+}
+
+fn reload_devices<P: Platform>(
+    platform: &mut P,
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    input_devices: &mut HashMap<PathBuf, P::Device>,
+    selection: &DeviceSelection,
+    watch_devices: bool,
+    grab: bool,
+) -> anyhow::Result<()> {
+    // A removed device won't release the keys it was holding
+    release_all(handler, dispatcher).context("Releasing keys on device reload")?;
+    for input_device in input_devices.values_mut() {
+        input_device.ungrab();
+    }
+    *input_devices = get_input_devices(platform, selection, watch_devices, grab)?;
+    Ok(())
+}
+
+// Release keys pressed by remapping, and forget keys held on input devices
+fn release_all(handler: &mut EventHandler, dispatcher: &mut ActionDispatcher) -> anyhow::Result<()> {
+    handler
+        .release_all()
+        .map_err(|e| anyhow!("Failed resetting the handler: {e:?}"))?;
+    Ok(dispatcher.release_all()?)
+}
+
+// Follow a change of EventHandler#suspend_state on devices, and return the new state.
+// Grabs are left alone while escape_chord has paused remapping.
+fn sync_suspend_state<D: InputSource>(
+    old_state: SuspendState,
+    handler: &EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    input_devices: &mut HashMap<PathBuf, D>,
+    running: bool,
+) -> anyhow::Result<SuspendState> {
+    let new_state = handler.suspend_state();
+    if new_state == old_state {
+        return Ok(new_state);
+    }
+    if old_state == SuspendState::Active {
+        // Keys pressed by remapping would stay pressed while it's suspended
+        dispatcher.release_all().context("Releasing keys on suspend")?;
+    }
+    if running && new_state == SuspendState::Ungrabbed {
+        for input_device in input_devices.values_mut() {
+            input_device.ungrab();
+        }
+    } else if running && old_state == SuspendState::Ungrabbed {
+        for input_device in input_devices.values_mut() {
+            input_device.grab();
+        }
+    }
+    Ok(new_state)
+}
+
+// Release keys and devices before exiting
+fn shutdown<D: InputSource>(
+    dispatcher: &mut ActionDispatcher,
+    input_devices: &mut HashMap<PathBuf, D>,
+) -> anyhow::Result<()> {
+    dispatcher.release_all().context("Releasing keys on exit")?;
+    for input_device in input_devices.values_mut() {
+        input_device.ungrab();
+    }
+    Ok(())
+}
+
+// Remap events from a device unless paused, and check chords
+#[allow(clippy::too_many_arguments)]
+fn handle_input_events(
+    input_device: &mut impl InputSource,
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    config: &mut Config,
+    recorder: &mut Option<Recorder>,
+    chords: &mut Chords,
+    timer_output: &mut Option<String>,
+    paused: bool,
+) -> anyhow::Result<InputStatus> {
+    let mut device_exists = true;
+    let events = match input_device.fetch_events().map_err(|e| (e.raw_os_error(), e)) {
+        Err((Some(ENODEV), _)) => {
+            device_exists = false;
+            Ok(Vec::new())
+        }
+        Err((_, error)) => Err(error).context("Error fetching input events"),
+        Ok(events) => Ok(events),
+    }?;
+    let status = if device_exists {
+        InputStatus::Handled
+    } else {
+        InputStatus::DeviceRemoved
+    };
+
+    // Checked before remapping, so that a broken config can't get in the way
+    let mut escape = false;
+    for event in &events {
+        escape |= chords.escape.on_event(event);
+    }
+    if escape {
+        return Ok(InputStatus::EscapeChord);
+    }
+    if paused {
+        return Ok(status);
+    }
+
+    if let Some(recorder) = recorder {
+        recorder
+            .record_events(&input_device.to_info(), &events)
+            .context("Error recording input events")?;
+    }
+    // Only a key press arms the timer of nested remaps
+    if events
+        .iter()
+        .any(|event| event.event_type() == EventType::KEY && event.value() == PRESS)
+    {
+        *timer_output = input_device.output().map(String::from);
+    }
+    let input_events = events.iter().map(|e| Event::new(input_device.to_info(), *e)).collect();
+    dispatcher.set_output(input_device.output());
+    let result = handle_events(handler, dispatcher, config, recorder, input_events);
+    dispatcher.set_output(None);
+    result?;
+    // Checked after remapping, so that the chord's own keys are released too
+    let mut panic = false;
+    for event in &events {
+        panic |= chords.panic.on_event(event);
+    }
+    if panic {
+        println!("Panic chord is pressed. Releasing all keys.");
+        release_all(handler, dispatcher).context("Releasing keys on panic chord")?;
+    }
+    Ok(status)
+}
+
+// Handle an Event with EventHandler, and dispatch Actions with ActionDispatcher
+fn handle_events(
+    handler: &mut EventHandler,
+    dispatcher: &mut ActionDispatcher,
+    config: &mut Config,
+    recorder: &mut Option<Recorder>,
+    events: Vec<Event>,
+) -> anyhow::Result<()> {
+    let actions = handler
+        .on_events(&events, config)
+        .map_err(|e| anyhow!("Failed handling {events:?}:\n  {e:?}"))?;
+    dispatch_actions(dispatcher, recorder, actions)
+}
+
+fn dispatch_actions(
+    dispatcher: &mut ActionDispatcher,
+    recorder: &mut Option<Recorder>,
+    actions: Vec<Action>,
+) -> anyhow::Result<()> {
+    for action in actions {
+        if let Some(recorder) = recorder {
+            recorder.record_action(&action).context("Error recording an action")?;
+        }
+        dispatcher.on_action(action)?;
+    }
+    Ok(())
+}
+
+// Add devices that have appeared under /dev/input
+fn handle_device_changes<P: Platform>(
+    events: Vec<WatchEvent>,
+    platform: &mut P,
+    input_devices: &mut HashMap<PathBuf, P::Device>,
+    selection: &DeviceSelection,
+    grab: bool,
+) -> anyhow::Result<()> {
+    input_devices.extend(events.into_iter().filter_map(|event| {
+        event.name.and_then(|name| {
+            let path = PathBuf::from("/dev/input/").join(name);
+            let mut device = platform.open_device(path.clone()).ok()?;
+            if device.select(selection, grab) {
+                device.print();
+                Some((path, device))
+            } else {
+                None
+            }
+        })
+    }));
+    Ok(())
+}
+
+// Return false if the config files have changed
+fn handle_config_changes<P: Platform>(
+    events: Vec<WatchEvent>,
+    platform: &mut P,
+    input_devices: &mut HashMap<PathBuf, P::Device>,
+    selection: &DeviceSelection,
+    config_paths: &[PathBuf],
+) -> anyhow::Result<bool> {
+    for event in &events {
+        match (event.mask, &event.name) {
+            // Dir events
+            (_, Some(name))
+                if config_paths
+                    .iter()
+                    .any(|p| name == p.file_name().expect("Config path has a file name")) =>
+            {
+                return Ok(false)
+            }
+            // File events
+            (mask, _) if mask.contains(AddWatchFlags::IN_MODIFY) => return Ok(false),
+            // Unrelated
+            _ => (),
+        }
+    }
+    handle_device_changes(events, platform, input_devices, selection, true)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::static_client::StaticClient;
+    use crate::client::WMClient;
+    use crate::clock::ManualClock;
+    use crate::config::device::Capability;
+    use crate::device::InputDeviceInfo;
+    use crate::tests::TempDir;
+    use evdev::{InputEvent, Key};
+    use indoc::indoc;
+    use std::cell::RefCell;
+    use std::collections::{HashSet, VecDeque};
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::{Duration, SystemTime};
+
+    // What happens next on the fake system
+    enum Step {
+        // A device sends key events
+        Input(&'static str, Vec<(Key, i32)>),
+        // A device appears under /dev/input, which --watch=device notices
+        Plug(&'static str, &'static str, Capability),
+        // A device goes away
+        Unplug(&'static str),
+        // Time passes, and the timer of nested remaps expires if it's due
+        Sleep(Duration),
+        // --watch=config notices a file in the directory of config files
+        ConfigCreated(&'static str),
+        // A config file is replaced with the content, like editors save files
+        ConfigWritten(PathBuf, &'static str),
+        Signal(Signal),
+    }
+
+    // Devices and outputs of the fake system, shared by the fakes and the test
+    #[derive(Default)]
+    struct World {
+        devices: HashMap<PathBuf, DeviceState>,
+        grabbed: HashSet<PathBuf>,
+        // Key events emitted by the default output device
+        emitted: Vec<(Key, i32)>,
+        // and by the ones of devices.options.output
+        emitted_to: HashMap<String, Vec<(Key, i32)>>,
+        // Names of the output devices that have been removed, and how many steps were left then
+        dropped_outputs: Vec<(String, usize)>,
+        clock: ManualClock,
+        device_changes: Vec<WatchEvent>,
+        config_changes: Vec<WatchEvent>,
+        steps: VecDeque<Step>,
+    }
+
+    struct DeviceState {
+        name: String,
+        capabilities: Vec<Capability>,
+        // Events that haven't been fetched yet
+        events: VecDeque<InputEvent>,
+    }
+
+    type SharedWorld = Rc<RefCell<World>>;
+
+    struct FakePlatform {
+        world: SharedWorld,
+    }
+
+    impl Platform for FakePlatform {
+        type Device = FakeDevice;
+
+        fn input_devices(&mut self) -> io::Result<Vec<FakeDevice>> {
+            let paths: Vec<PathBuf> = self.world.borrow().devices.keys().cloned().collect();
+            paths.into_iter().map(|path| self.open_device(path)).collect()
+        }
+
+        fn open_device(&mut self, path: PathBuf) -> io::Result<FakeDevice> {
+            let world = self.world.borrow();
+            let device = world.devices.get(&path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(FakeDevice {
+                world: self.world.clone(),
+                name: device.name.clone(),
+                capabilities: device.capabilities.clone(),
+                grab_enabled: true,
+                output: None,
+                path,
+            })
+        }
+
+        fn output_device(
+            &mut self,
+            _bus_type: Option<BusType>,
+            _mouse: bool,
+            output: Option<&str>,
+        ) -> Result<Box<dyn OutputDevice>, Box<dyn Error>> {
+            Ok(Box::new(FakeOutput {
+                world: self.world.clone(),
+                name: output.map(String::from),
+            }))
+        }
+
+        // Take the next step, or exit when the steps are over
+        fn wait(&mut self, _devices: &HashMap<PathBuf, FakeDevice>) -> anyhow::Result<Readiness> {
+            let mut world = self.world.borrow_mut();
+            let mut readiness = Readiness::default();
+            match world.steps.pop_front().unwrap_or(Step::Signal(Signal::SIGTERM)) {
+                Step::Input(path, keys) => {
+                    let device = world.devices.get_mut(Path::new(path)).expect("the device is plugged");
+                    let events = keys
+                        .into_iter()
+                        .map(|(key, value)| InputEvent::new(EventType::KEY, key.code(), value));
+                    device.events.extend(events);
+                    readiness.devices.push(PathBuf::from(path));
+                }
+                Step::Plug(path, name, capability) => {
+                    let device = DeviceState {
+                        name: name.to_string(),
+                        capabilities: vec![capability],
+                        events: VecDeque::new(),
+                    };
+                    world.devices.insert(PathBuf::from(path), device);
+                    world.device_changes.push(WatchEvent {
+                        mask: AddWatchFlags::IN_CREATE,
+                        name: Path::new(path).file_name().map(OsString::from),
+                    });
+                }
+                Step::Unplug(path) => {
+                    world.devices.remove(Path::new(path));
+                    world.grabbed.remove(Path::new(path));
+                    readiness.devices.push(PathBuf::from(path));
+                }
+                Step::Sleep(duration) => {
+                    world.clock.advance(duration);
+                    readiness.timer = world.clock.take_expired_timer();
+                }
+                Step::ConfigCreated(name) => world.config_changes.push(WatchEvent {
+                    mask: AddWatchFlags::IN_CREATE,
+                    name: Some(OsString::from(name)),
+                }),
+                Step::ConfigWritten(path, content) => {
+                    fs::write(&path, content)?;
+                    // Don't depend on the resolution of mtime
+                    File::options()
+                        .write(true)
+                        .open(&path)?
+                        .set_modified(SystemTime::now() + Duration::from_secs(60))?;
+                    world.config_changes.push(WatchEvent {
+                        mask: AddWatchFlags::IN_MOVED_TO,
+                        name: path.file_name().map(OsString::from),
+                    });
+                }
+                Step::Signal(signal) => readiness.signal = Some(signal),
+            }
+            Ok(readiness)
+        }
+
+        fn device_changes(&mut self) -> Vec<WatchEvent> {
+            self.world.borrow_mut().device_changes.drain(..).collect()
+        }
+
+        fn config_changes(&mut self) -> Vec<WatchEvent> {
+            self.world.borrow_mut().config_changes.drain(..).collect()
+        }
+
+        fn clock(&self) -> Box<dyn Clock> {
+            Box::new(self.world.borrow().clock.clone())
+        }
+    }
+
+    struct FakeDevice {
+        world: SharedWorld,
+        path: PathBuf,
+        name: String,
+        capabilities: Vec<Capability>,
+        grab_enabled: bool,
+        output: Option<String>,
+    }
+
+    impl InputSource for FakeDevice {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn grab(&mut self) -> bool {
+            if self.grab_enabled {
+                self.world.borrow_mut().grabbed.insert(self.path.clone());
+            }
+            true
+        }
+
+        fn ungrab(&mut self) {
+            self.world.borrow_mut().grabbed.remove(&self.path);
+        }
+
+        fn fetch_events(&mut self) -> io::Result<Vec<InputEvent>> {
+            match self.world.borrow_mut().devices.get_mut(&self.path) {
+                Some(device) => Ok(device.events.drain(..).collect()),
+                None => Err(io::Error::from_raw_os_error(ENODEV)),
+            }
+        }
+
+        fn select(&mut self, selection: &DeviceSelection, grab: bool) -> bool {
+            let info = self.to_info();
+            let keyboard = self.capabilities.contains(&Capability::Keyboard);
+            let mouse = self.capabilities.contains(&Capability::Mouse);
+            if !selection.includes(&info, keyboard, mouse) {
+                return false;
+            }
+            let options = selection.options_for(&info);
+            self.grab_enabled = options.grab;
+            self.output = options.output;
+            !grab || self.grab()
+        }
+
+        fn output(&self) -> Option<&str> {
+            self.output.as_deref()
+        }
+
+        fn bus_type(&self) -> BusType {
+            BusType::BUS_USB
+        }
+
+        fn to_info(&self) -> InputDeviceInfo<'_> {
+            InputDeviceInfo {
+                capabilities: &self.capabilities,
+                ..InputDeviceInfo::new(&self.name, &self.path)
+            }
+        }
+
+        fn print(&self) {
+            println!("{:18}: {}", self.path.display(), self.name)
+        }
+    }
+
+    struct FakeOutput {
+        world: SharedWorld,
+        name: Option<String>,
+    }
+
+    impl OutputDevice for FakeOutput {
+        fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+            let keys = events
+                .iter()
+                .filter(|event| event.event_type() == EventType::KEY)
+                .map(|event| (Key::new(event.code()), event.value()));
+            let mut world = self.world.borrow_mut();
+            match &self.name {
+                Some(name) => world.emitted_to.entry(name.clone()).or_default().extend(keys),
+                None => world.emitted.extend(keys),
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for FakeOutput {
+        fn drop(&mut self) {
+            if let Some(name) = &self.name {
+                let mut world = self.world.borrow_mut();
+                let steps = world.steps.len();
+                world.dropped_outputs.push((name.clone(), steps));
+            }
+        }
+    }
+
+    const KEYBOARD: &str = "/dev/input/event0";
+    const RELEASE: i32 = 0;
+
+    // A config file of each test, which may run in parallel. It's removed when the TempDir is dropped.
+    fn write_config(name: &str, config_yaml: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        let path = dir.path.join("config.yml");
+        fs::write(&path, config_yaml).unwrap();
+        (dir, path)
+    }
+
+    // Run the loop with --watch=device,config on a system with a keyboard until the steps are over
+    fn run_steps(config_path: &Path, window: Option<&str>, steps: Vec<Step>) -> World {
+        let world = Rc::new(RefCell::new(World {
+            steps: steps.into(),
+            ..Default::default()
+        }));
+        let keyboard = DeviceState {
+            name: String::from("Fake Keyboard"),
+            capabilities: vec![Capability::Keyboard],
+            events: VecDeque::new(),
+        };
+        world.borrow_mut().devices.insert(PathBuf::from(KEYBOARD), keyboard);
+
+        let config_paths = vec![config_path.to_path_buf()];
+        let config = load_configs(&config_paths).unwrap();
+        let client = StaticClient {
+            current_application: None,
+            current_window: window.map(String::from),
+            window_properties: Default::default(),
+        };
+        let platform = FakePlatform { world: world.clone() };
+        let handler = EventHandler::new(
+            Box::new(world.borrow().clock.timer()),
+            platform.clock(),
+            &config.default_mode,
+            Duration::ZERO,
+            WMClient::new("static", Box::new(client)),
+        );
+        let args = DeviceArgs {
+            device: vec![],
+            ignore: vec![],
+            mouse: None,
+            watch: true,
+        };
+        EventLoop::new(platform, config, config_paths, args, handler, None)
+            .unwrap()
+            .run()
+            .unwrap();
+        world.take()
+    }
+
+    #[test]
+    fn test_remap_until_shutdown() {
+        let (_dir, config) = write_config("remap_until_shutdown", "modmap: [{ remap: { a: b } }]");
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS)]),
+                Step::Signal(Signal::SIGINT),
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, RELEASE)]),
+            ],
+        );
+        // The key pressed by remapping is released on exit, and the rest of the steps is never taken
+        assert_eq!(vec![(Key::KEY_B, PRESS), (Key::KEY_B, RELEASE)], world.emitted);
+        assert!(world.grabbed.is_empty());
+        assert_eq!(1, world.steps.len());
+    }
+
+    #[test]
+    fn test_hot_plug() {
+        let (_dir, config) = write_config("hot_plug", "modmap: [{ remap: { a: b } }]");
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Plug("/dev/input/event1", "Another Keyboard", Capability::Keyboard),
+                Step::Plug("/dev/input/event2", "Fake Mouse", Capability::Mouse),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]),
+                // Mice aren't selected without --mouse
+                Step::Input("/dev/input/event2", vec![(Key::BTN_LEFT, PRESS), (Key::BTN_LEFT, RELEASE)]),
+            ],
+        );
+        assert_eq!(vec![(Key::KEY_B, PRESS), (Key::KEY_B, RELEASE)], world.emitted);
+    }
+
+    #[test]
+    fn test_unplug_releases_keys() {
+        let (_dir, config) = write_config("unplug_releases_keys", "modmap: [{ remap: { a: b } }]");
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS)]),
+                Step::Unplug(KEYBOARD),
+                Step::Plug(KEYBOARD, "Fake Keyboard", Capability::Keyboard),
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]),
+            ],
+        );
+        assert_eq!(
+            vec![
+                (Key::KEY_B, PRESS),
+                (Key::KEY_B, RELEASE),
+                (Key::KEY_B, PRESS),
+                (Key::KEY_B, RELEASE)
+            ],
+            world.emitted
+        );
+    }
+
+    #[test]
+    fn test_unplug_releases_modifiers() {
+        let (_dir, config) = write_config("unplug_releases_modifiers", "keymap: [{ remap: { Shift-a: c } }]");
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Plug("/dev/input/event1", "Another Keyboard", Capability::Keyboard),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_LEFTSHIFT, PRESS)]),
+                Step::Unplug("/dev/input/event1"),
+                // Shift-a doesn't match without the release of Shift from the removed device
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]),
+            ],
+        );
+        assert_eq!(
+            vec![
+                (Key::KEY_LEFTSHIFT, PRESS),
+                (Key::KEY_LEFTSHIFT, RELEASE),
+                (Key::KEY_A, PRESS),
+                (Key::KEY_A, RELEASE)
+            ],
+            world.emitted
+        );
+    }
+
+    #[test]
+    fn test_pause_forgets_modifiers() {
+        let (_dir, config) = write_config(
+            "pause_forgets_modifiers",
+            indoc! {"
+            escape_chord: { keys: [Esc], hold_millis: 0, action: pause }
+            keymap: [{ remap: { Shift-a: c } }]
+            "},
+        );
+        let escape = || Step::Input(KEYBOARD, vec![(Key::KEY_ESC, PRESS), (Key::KEY_ESC, RELEASE)]);
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Input(KEYBOARD, vec![(Key::KEY_LEFTSHIFT, PRESS)]),
+                escape(),
+                Step::Input(KEYBOARD, vec![(Key::KEY_LEFTSHIFT, RELEASE)]),
+                escape(),
+                // Resumes once the chord is released
+                Step::Sleep(Duration::ZERO),
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]),
+            ],
+        );
+        assert_eq!(
+            vec![
+                (Key::KEY_LEFTSHIFT, PRESS),
+                (Key::KEY_LEFTSHIFT, RELEASE),
+                (Key::KEY_A, PRESS),
+                (Key::KEY_A, RELEASE)
+            ],
+            world.emitted
+        );
+    }
+
+    #[test]
+    fn test_override_timeout() {
+        let (_dir, config) = write_config(
+            "override_timeout",
+            indoc! {"
+            keymap:
+              - remap:
+                  C-x:
+                    remap:
+                      s: C-w
+                    timeout_key: Down
+                    timeout_millis: 1000
+            "},
+        );
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Input(KEYBOARD, vec![(Key::KEY_LEFTCTRL, PRESS), (Key::KEY_X, PRESS)]),
+                Step::Input(KEYBOARD, vec![(Key::KEY_X, RELEASE), (Key::KEY_LEFTCTRL, RELEASE)]),
+                Step::Sleep(Duration::from_millis(999)),
+                Step::Sleep(Duration::from_millis(1)),
+                // The nested remap has timed out
+                Step::Input(KEYBOARD, vec![(Key::KEY_S, PRESS), (Key::KEY_S, RELEASE)]),
+            ],
+        );
+        assert_eq!(
+            vec![
+                (Key::KEY_LEFTCTRL, PRESS),
+                (Key::KEY_X, RELEASE),
+                (Key::KEY_LEFTCTRL, RELEASE),
+                (Key::KEY_DOWN, PRESS),
+                (Key::KEY_DOWN, RELEASE),
+                (Key::KEY_S, PRESS),
+                (Key::KEY_S, RELEASE),
+            ],
+            world.emitted
+        );
+    }
+
+    #[test]
+    fn test_suspend_timeout() {
+        let (_dir, config) = write_config(
+            "suspend_timeout",
+            indoc! {"
+            keymap:
+              - remap:
+                  F1: { suspend: { ungrab: true, timeout_millis: 1000 } }
+                  a: b
+            "},
+        );
+        let tap = || Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]);
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Input(KEYBOARD, vec![(Key::KEY_F1, PRESS), (Key::KEY_F1, RELEASE)]),
+                // Left to the ungrabbed device
+                tap(),
+                // Resumes on the timer, not on the next key
+                Step::Sleep(Duration::from_millis(1000)),
+                tap(),
+            ],
+        );
+        // keymap lets the release of the original key through
+        assert_eq!(vec![(Key::KEY_B, PRESS), (Key::KEY_B, RELEASE), (Key::KEY_A, RELEASE)], world.emitted);
+    }
+
+    #[test]
+    fn test_output_of_timeout() {
+        let (_dir, config) = write_config(
+            "output_of_timeout",
+            indoc! {"
+            devices:
+              options:
+                - device: Another Keyboard
+                  output: another
+            keymap:
+              - remap:
+                  C-x:
+                    remap:
+                      s: C-w
+                    timeout_key: Down
+                    timeout_millis: 1000
+            "},
+        );
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Plug("/dev/input/event1", "Another Keyboard", Capability::Keyboard),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_LEFTCTRL, PRESS), (Key::KEY_X, PRESS)]),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_X, RELEASE), (Key::KEY_LEFTCTRL, RELEASE)]),
+                // A release from another device doesn't take over the timeout
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, RELEASE)]),
+                Step::Sleep(Duration::from_millis(1000)),
+                Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]),
+            ],
+        );
+        assert_eq!(
+            Some(&vec![
+                (Key::KEY_LEFTCTRL, PRESS),
+                (Key::KEY_X, RELEASE),
+                (Key::KEY_LEFTCTRL, RELEASE),
+                (Key::KEY_DOWN, PRESS),
+                (Key::KEY_DOWN, RELEASE),
+            ]),
+            world.emitted_to.get("another")
+        );
+        assert_eq!(vec![(Key::KEY_A, RELEASE), (Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)], world.emitted);
+    }
+
+    #[test]
+    fn test_remove_output() {
+        let (_dir, config) = write_config(
+            "remove_output",
+            indoc! {"
+            devices:
+              options:
+                - device: Another Keyboard
+                  output: another
+            "},
+        );
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                Step::Plug("/dev/input/event1", "Another Keyboard", Capability::Keyboard),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_LEFTSHIFT, PRESS)]),
+                Step::ConfigWritten(config.clone(), "modmap: []"),
+                Step::Input("/dev/input/event1", vec![(Key::KEY_LEFTSHIFT, RELEASE)]),
+            ],
+        );
+        // The output releases the key before it's removed
+        assert_eq!(
+            Some(&vec![(Key::KEY_LEFTSHIFT, PRESS), (Key::KEY_LEFTSHIFT, RELEASE)]),
+            world.emitted_to.get("another")
+        );
+        // It's removed by the reload, not by the shutdown
+        assert_eq!(vec![(String::from("another"), 1)], world.dropped_outputs);
+        assert_eq!(vec![(Key::KEY_LEFTSHIFT, RELEASE)], world.emitted);
+    }
+
+    #[test]
+    fn test_config_reload() {
+        let (_dir, config) = write_config("config_reload", "modmap: [{ remap: { a: b } }]");
+        let tap = || Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)]);
+        let world = run_steps(
+            &config,
+            None,
+            vec![
+                tap(),
+                // Unrelated files don't reload the config
+                Step::ConfigCreated("config_reload.yml.swp"),
+                tap(),
+                Step::ConfigWritten(config.clone(), "modmap: [{ remap: { a: c } }]"),
+                tap(),
+                // Neither does the same config
+                Step::ConfigCreated("config_reload.yml"),
+                tap(),
+            ],
+        );
+        let taps = |key| [(key, PRESS), (key, RELEASE)];
+        assert_eq!([taps(Key::KEY_B), taps(Key::KEY_B), taps(Key::KEY_C), taps(Key::KEY_C)].concat(), world.emitted);
+    }
+
+    #[test]
+    fn test_window_condition() {
+        let (_dir, config) = write_config(
+            "window_condition",
+            indoc! {"
+            modmap:
+              - window:
+                  only: /Firefox/
+                remap:
+                  a: b
+            "},
+        );
+        let steps = || vec![Step::Input(KEYBOARD, vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)])];
+        let world = run_steps(&config, Some("Mozilla Firefox"), steps());
+        assert_eq!(vec![(Key::KEY_B, PRESS), (Key::KEY_B, RELEASE)], world.emitted);
+        let world = run_steps(&config, Some("Terminal"), steps());
+        assert_eq!(vec![(Key::KEY_A, PRESS), (Key::KEY_A, RELEASE)], world.emitted);
+    }
+}
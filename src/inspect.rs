@@ -0,0 +1,113 @@
+use crate::action::Action;
+use crate::client::build_client;
+use crate::client::push_service::start_push_service;
+use crate::clock::SystemClock;
+use crate::config::device::DeviceMatcher;
+use crate::config::key::key_name;
+use crate::config::Config;
+use crate::device::{get_input_devices, DeviceSelection};
+use crate::event::Event;
+use crate::event_handler::{disguised_key, EventHandler};
+use crate::select_readable;
+use anyhow::{anyhow, Context};
+use evdev::RelativeAxisType;
+use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+pub struct InspectOptions {
+    pub device: Vec<DeviceMatcher>,
+    pub ignore: Vec<DeviceMatcher>,
+    pub mouse: Option<bool>,
+}
+
+// xremap inspect: Like evtest, but print key names in the config syntax and what the config does with them.
+// Devices aren't grabbed, so the original events still reach other applications.
+pub fn inspect(config: &Config, options: InspectOptions) -> anyhow::Result<()> {
+    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
+    let timer_fd = timer.as_raw_fd();
+    let delay = Duration::from_millis(config.keypress_delay_ms);
+    let mut client = build_client();
+    client.accept_pushes(start_push_service());
+    let mut handler = EventHandler::new(Box::new(timer), Box::new(SystemClock), &config.default_mode, delay, client);
+    handler.inspect_matches();
+    let selection = DeviceSelection::new(&config.devices, &options.device, &options.ignore, options.mouse);
+    let mut input_devices = get_input_devices(&selection, false, false)?;
+    println!("Press keys to inspect them. Devices are not grabbed. Press Ctrl-C to exit.");
+
+    loop {
+        let readable_fds = select_readable(input_devices.values(), &[], &[timer_fd])?;
+        if readable_fds.contains(timer_fd) {
+            println!("timeout of nested remap or suspend");
+            inspect_event(&mut handler, config, Event::OverrideTimeout)?;
+        }
+        for input_device in input_devices.values_mut() {
+            if !readable_fds.contains(input_device.as_raw_fd()) {
+                continue;
+            }
+            let events: Vec<_> = input_device
+                .fetch_events()
+                .context("Error fetching input events")?
+                .collect();
+            let device = input_device.to_info();
+            for event in events {
+                let event = Event::new(input_device.to_info(), event);
+                let description = match &event {
+                    Event::KeyEvent(_, key_event) if key_event.value() != REPEAT => {
+                        let value = if key_event.value() == PRESS { "press" } else { "release" };
+                        format!("{} {}", key_name(key_event.key), value)
+                    }
+                    // Skip mouse movements, which would flood the output
+                    Event::RelativeEvent(_, relative_event)
+                        if ![RelativeAxisType::REL_X.0, RelativeAxisType::REL_Y.0].contains(&relative_event.code) =>
+                    {
+                        format!(
+                            "{} ({:?} {})",
+                            key_name(disguised_key(relative_event)),
+                            RelativeAxisType(relative_event.code),
+                            relative_event.value
+                        )
+                    }
+                    _ => {
+                        // Keep the handler's state in sync without printing anything
+                        handler
+                            .on_events(&vec![event], config)
+                            .map_err(|e| anyhow!("Failed handling an event: {e:?}"))?;
+                        handler.take_matched_entries();
+                        continue;
+                    }
+                };
+                let (application, window) = handler.focused_window();
+                println!(
+                    "{} from '{}' (application: {}, window: {})",
+                    description,
+                    device.name,
+                    application.as_deref().unwrap_or("<unknown>"),
+                    window.as_deref().unwrap_or("<unknown>"),
+                );
+                inspect_event(&mut handler, config, event)?;
+            }
+        }
+    }
+}
+
+// Handle an event and print the matched entries and the resulting actions
+fn inspect_event(handler: &mut EventHandler, config: &Config, event: Event) -> anyhow::Result<()> {
+    let actions = handler
+        .on_events(&vec![event], config)
+        .map_err(|e| anyhow!("Failed handling an event: {e:?}"))?;
+    for entry in handler.take_matched_entries() {
+        println!("  matched {}", entry);
+    }
+    for action in actions {
+        match action {
+            Action::Delay(_) | Action::InputEvent(_) => {}
+            action => println!("  => {:?}", action),
+        }
+    }
+    Ok(())
+}
+
+// InputEvent#value
+const PRESS: i32 = 1;
+const REPEAT: i32 = 2;
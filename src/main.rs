@@ -1,51 +1,75 @@
-use crate::config::Config;
-use crate::device::{device_watcher, get_input_devices, output_device};
+use crate::config::device::DeviceMatcher;
+use crate::device::{device_watcher, InputDevice};
 use crate::event_handler::EventHandler;
-use action_dispatcher::ActionDispatcher;
-use anyhow::{anyhow, bail, Context};
-use clap::{CommandFactory, Parser, ValueEnum};
+use crate::event_loop::{DeviceArgs, EventLoop, Platform, SystemPlatform};
+use anyhow::{bail, Context};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use client::build_client;
+use client::external_client::build_external_client;
+use client::push_service::start_push_service;
+use config::check::check_config;
 use config::{config_watcher, load_configs};
-use device::InputDevice;
-use event::Event;
-use nix::libc::ENODEV;
-use nix::sys::inotify::{AddWatchFlags, Inotify, InotifyEvent};
+use inspect::{inspect, InspectOptions};
+use nix::sys::inotify::Inotify;
 use nix::sys::select::select;
 use nix::sys::select::FdSet;
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
 use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
-use std::collections::HashMap;
-use std::io::stdout;
+use recorder::Recorder;
+use simulate::{simulate, SimulateOptions, SimulateOutput};
+use std::io::{stdin, stdout};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
 use std::time::Duration;
 
 mod action;
 mod action_dispatcher;
+mod chord;
 mod client;
+mod clock;
 mod config;
 mod device;
 mod event;
 mod event_handler;
+mod event_loop;
+mod inspect;
+mod recorder;
+mod simulate;
 #[cfg(test)]
 mod tests;
 
 #[derive(Parser, Debug)]
-#[clap(version)]
+#[clap(version, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Include a device name or path
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Include a device name, path, or id:, bus:, phys:, uniq:, has: matcher
     #[clap(long, use_value_delimiter = true)]
-    device: Vec<String>,
-    /// Ignore a device name or path
+    device: Vec<DeviceMatcher>,
+    /// Ignore a device name, path, or id:, bus:, phys:, uniq:, has: matcher
     #[clap(long, use_value_delimiter = true)]
-    ignore: Vec<String>,
-    /// Match mice by default
-    #[clap(long)]
-    mouse: bool,
+    ignore: Vec<DeviceMatcher>,
+    /// Match mice by default. --mouse=false overrides `mouse: true` of the config
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    mouse: Option<bool>,
     /// Targets to watch
     #[clap(long, value_enum, num_args = 0.., use_value_delimiter = true, require_equals = true,
            default_missing_value = "device", verbatim_doc_comment)]
     watch: Vec<WatchTargets>,
+    /// Run a shell command to get the focused application and window title,
+    /// instead of the application client of the cargo feature.
+    /// It prints them on two lines, or {"application": ..., "window": ..., "pid": ...}
+    #[clap(long, value_name = "COMMAND", conflicts_with = "focus_pipe", verbatim_doc_comment)]
+    focus_command: Option<String>,
+    /// Read the focused application and window title as JSON lines
+    /// like --focus-command from a named pipe or a Unix socket
+    #[clap(long, value_name = "PATH", verbatim_doc_comment)]
+    focus_pipe: Option<PathBuf>,
+    /// Record input events and dispatched actions to a file as JSON lines
+    #[clap(long, value_name = "FILE")]
+    record: Option<PathBuf>,
     /// Generate shell completions
     ///
     /// You can use them by storing in your shells completion file or by running
@@ -58,6 +82,53 @@ struct Args {
     configs: Vec<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate config file(s) without grabbing devices
+    Check {
+        /// Config file(s)
+        #[clap(required = true, num_args = 1..)]
+        configs: Vec<PathBuf>,
+    },
+    /// Replay key events from stdin without grabbing devices or using uinput
+    ///
+    /// Each line of stdin is `KEY press|release|repeat`, `KEY` (press and release),
+    /// `REL_AXIS value`, or `sleep MILLIS`. Lines after a `#` are ignored.
+    #[clap(verbatim_doc_comment)]
+    Simulate {
+        /// Config file(s)
+        #[clap(required = true, num_args = 1..)]
+        configs: Vec<PathBuf>,
+        /// Application name to pretend is focused
+        #[clap(long)]
+        application: Option<String>,
+        /// Window title to pretend is focused
+        #[clap(long)]
+        window: Option<String>,
+        /// Device name to pretend events come from
+        #[clap(long, default_value = "xremap simulate")]
+        device: String,
+        /// What to print
+        #[clap(long, value_enum, default_value = "actions")]
+        output: SimulateOutput,
+    },
+    /// Print key names and what the config does with them, without grabbing devices
+    Inspect {
+        /// Config file(s)
+        #[clap(required = true, num_args = 1..)]
+        configs: Vec<PathBuf>,
+        /// Include a device name, path, or id:, bus:, phys:, uniq:, has: matcher
+        #[clap(long, use_value_delimiter = true)]
+        device: Vec<DeviceMatcher>,
+        /// Ignore a device name, path, or id:, bus:, phys:, uniq:, has: matcher
+        #[clap(long, use_value_delimiter = true)]
+        ignore: Vec<DeviceMatcher>,
+        /// Match mice by default. --mouse=false overrides `mouse: true` of the config
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        mouse: Option<bool>,
+    },
+}
+
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
 enum WatchTargets {
     /// add new devices automatically
@@ -66,20 +137,18 @@ enum WatchTargets {
     Config,
 }
 
-// TODO: Unify this with Event
-enum ReloadEvent {
-    ReloadConfig,
-    ReloadDevices,
-}
-
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
     let Args {
+        command,
         device: device_filter,
         ignore: ignore_filter,
         mouse,
         watch,
+        focus_command,
+        focus_pipe,
+        record,
         configs,
         completions,
     } = Args::parse();
@@ -88,6 +157,41 @@ fn main() -> anyhow::Result<()> {
         clap_complete::generate(shell, &mut Args::command(), "xremap", &mut stdout());
         return Ok(());
     }
+    match command {
+        Some(Command::Check { configs }) => return check_configs(&configs),
+        Some(Command::Simulate {
+            configs,
+            application,
+            window,
+            device,
+            output,
+        }) => {
+            let config = match load_configs(&configs) {
+                Ok(config) => config,
+                Err(e) => bail!("{}", e),
+            };
+            let options = SimulateOptions {
+                application,
+                window,
+                device,
+                output,
+            };
+            return simulate(&config, stdin().lock(), &mut stdout().lock(), options);
+        }
+        Some(Command::Inspect {
+            configs,
+            device,
+            ignore,
+            mouse,
+        }) => {
+            let config = match load_configs(&configs) {
+                Ok(config) => config,
+                Err(e) => bail!("{}", e),
+            };
+            return inspect(&config, InspectOptions { device, ignore, mouse });
+        }
+        None => {}
+    }
 
     // Configuration
     let config_paths = match configs[..] {
@@ -95,7 +199,7 @@ fn main() -> anyhow::Result<()> {
         _ => configs,
     };
 
-    let mut config = match config::load_configs(&config_paths) {
+    let config = match config::load_configs(&config_paths) {
         Ok(config) => config,
         Err(e) => bail!(
             "Failed to load config '{}': {}",
@@ -111,104 +215,59 @@ fn main() -> anyhow::Result<()> {
     let watch_config = watch.contains(&WatchTargets::Config);
 
     // Event listeners
+    // Block signals before build_client may spawn threads, which inherit the signal mask
+    let signal_fd = signal_watcher().context("Setting up signal watcher")?;
     let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty())?;
     let timer_fd = timer.as_raw_fd();
     let delay = Duration::from_millis(config.keypress_delay_ms);
-    let mut input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
-        Ok(input_devices) => input_devices,
-        Err(e) => bail!("Failed to prepare input devices: {}", e),
-    };
     let device_watcher = device_watcher(watch_devices).context("Setting up device watcher")?;
     let config_watcher = config_watcher(watch_config, &config_paths).context("Setting up config watcher")?;
-    let watchers: Vec<_> = device_watcher.iter().chain(config_watcher.iter()).collect();
-    let mut handler = EventHandler::new(timer, &config.default_mode, delay, build_client());
-    let output_device = match output_device(input_devices.values().next().map(InputDevice::bus_type), mouse) {
-        Ok(output_device) => output_device,
-        Err(e) => bail!("Failed to prepare an output device: {}", e),
+    let platform = SystemPlatform::new(timer_fd, signal_fd, device_watcher, config_watcher);
+    let mut client = build_external_client(focus_command, focus_pipe).unwrap_or_else(build_client);
+    client.accept_pushes(start_push_service());
+    let handler = EventHandler::new(Box::new(timer), platform.clock(), &config.default_mode, delay, client);
+    let recorder = match record {
+        Some(path) => Some(Recorder::new(&path)?),
+        None => None,
+    };
+    let args = DeviceArgs {
+        device: device_filter,
+        ignore: ignore_filter,
+        mouse,
+        watch: watch_devices,
     };
-    let mut dispatcher = ActionDispatcher::new(output_device);
 
     // Main loop
-    loop {
-        match 'event_loop: loop {
-            let readable_fds = select_readable(input_devices.values(), &watchers, timer_fd)?;
-            if readable_fds.contains(timer_fd) {
-                if let Err(error) =
-                    handle_events(&mut handler, &mut dispatcher, &mut config, vec![Event::OverrideTimeout])
-                {
-                    println!("Error on remap timeout: {error}")
-                }
-            }
-
-            for input_device in input_devices.values_mut() {
-                if !readable_fds.contains(input_device.as_raw_fd()) {
-                    continue;
-                }
-
-                if !handle_input_events(input_device, &mut handler, &mut dispatcher, &mut config)? {
-                    println!("Found a removed device. Reselecting devices.");
-                    break 'event_loop ReloadEvent::ReloadDevices;
-                }
-            }
+    EventLoop::new(platform, config, config_paths, args, handler, recorder)?.run()
+}
 
-            if let Some(inotify) = device_watcher {
-                if let Ok(events) = inotify.read_events() {
-                    handle_device_changes(events, &mut input_devices, &device_filter, &ignore_filter, mouse)?;
-                }
-            }
-            if let Some(inotify) = config_watcher {
-                if let Ok(events) = inotify.read_events() {
-                    if !handle_config_changes(
-                        events,
-                        &mut input_devices,
-                        &device_filter,
-                        &ignore_filter,
-                        mouse,
-                        &config_paths,
-                    )? {
-                        break 'event_loop ReloadEvent::ReloadConfig;
-                    }
-                }
-            }
-        } {
-            ReloadEvent::ReloadDevices => {
-                for input_device in input_devices.values_mut() {
-                    input_device.ungrab();
-                }
-                input_devices = match get_input_devices(&device_filter, &ignore_filter, mouse, watch_devices) {
-                    Ok(input_devices) => input_devices,
-                    Err(e) => bail!("Failed to prepare input devices: {}", e),
-                };
-            }
-            ReloadEvent::ReloadConfig => {
-                match (
-                    config.modify_time,
-                    config_paths
-                        .iter()
-                        .map(|p| p.metadata().ok()?.modified().ok())
-                        .flatten()
-                        .max(),
-                ) {
-                    (Some(last_mtime), Some(current_mtim)) if last_mtime == current_mtim => continue,
-                    _ => {
-                        if let Ok(c) = load_configs(&config_paths) {
-                            println!("Reloading Config");
-                            config = c;
-                        }
-                    }
-                }
-            }
-        }
+// xremap check: Load configs like the daemon does, and report problems without grabbing devices
+fn check_configs(config_paths: &Vec<PathBuf>) -> anyhow::Result<()> {
+    let config = match load_configs(config_paths) {
+        Ok(config) => config,
+        Err(e) => bail!("{}", e),
+    };
+    let warnings = check_config(&config);
+    for warning in &warnings {
+        println!("warning: {warning}");
+    }
+    if warnings.is_empty() {
+        println!("OK");
+    } else {
+        println!("{} warning(s)", warnings.len());
     }
+    Ok(())
 }
 
 fn select_readable<'a>(
     devices: impl Iterator<Item = &'a InputDevice>,
     watchers: &[&Inotify],
-    timer_fd: RawFd,
+    fds: &[RawFd],
 ) -> anyhow::Result<FdSet> {
     let mut read_fds = FdSet::new();
-    read_fds.insert(timer_fd);
+    for fd in fds {
+        read_fds.insert(*fd);
+    }
     for device in devices {
         read_fds.insert(device.as_raw_fd());
     }
@@ -219,100 +278,14 @@ fn select_readable<'a>(
     Ok(read_fds)
 }
 
-// Return false when a removed device is found.
-fn handle_input_events(
-    input_device: &mut InputDevice,
-    handler: &mut EventHandler,
-    dispatcher: &mut ActionDispatcher,
-    config: &mut Config,
-) -> anyhow::Result<bool> {
-    let mut device_exists = true;
-    let events = match input_device.fetch_events().map_err(|e| (e.raw_os_error(), e)) {
-        Err((Some(ENODEV), _)) => {
-            device_exists = false;
-            Ok(Vec::new())
-        }
-        Err((_, error)) => Err(error).context("Error fetching input events"),
-        Ok(events) => Ok(events.collect()),
-    }?;
-    let input_events = events.iter().map(|e| Event::new(input_device.to_info(), *e)).collect();
-    handle_events(handler, dispatcher, config, input_events)?;
-    Ok(device_exists)
-}
-
-// Handle an Event with EventHandler, and dispatch Actions with ActionDispatcher
-fn handle_events(
-    handler: &mut EventHandler,
-    dispatcher: &mut ActionDispatcher,
-    config: &mut Config,
-    events: Vec<Event>,
-) -> anyhow::Result<()> {
-    let actions = handler
-        .on_events(&events, config)
-        .map_err(|e| anyhow!("Failed handling {events:?}:\n  {e:?}"))?;
-    for action in actions {
-        dispatcher.on_action(action)?;
-    }
-    Ok(())
-}
-
-fn handle_device_changes(
-    events: Vec<InotifyEvent>,
-    input_devices: &mut HashMap<PathBuf, InputDevice>,
-    device_filter: &[String],
-    ignore_filter: &[String],
-    mouse: bool,
-) -> anyhow::Result<()> {
-    input_devices.extend(events.into_iter().filter_map(|event| {
-        event.name.and_then(|name| {
-            let path = PathBuf::from("/dev/input/").join(name);
-            let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
-                device.print();
-                Some(device.into())
-            } else {
-                None
-            }
-        })
-    }));
-    Ok(())
-}
-
-fn handle_config_changes(
-    events: Vec<InotifyEvent>,
-    input_devices: &mut HashMap<PathBuf, InputDevice>,
-    device_filter: &[String],
-    ignore_filter: &[String],
-    mouse: bool,
-    config_paths: &Vec<PathBuf>,
-) -> anyhow::Result<bool> {
-    for event in &events {
-        match (event.mask, &event.name) {
-            // Dir events
-            (_, Some(name))
-                if config_paths
-                    .iter()
-                    .any(|p| name == p.file_name().expect("Config path has a file name")) =>
-            {
-                return Ok(false)
-            }
-            // File events
-            (mask, _) if mask.contains(AddWatchFlags::IN_MODIFY) => return Ok(false),
-            // Unrelated
-            _ => (),
-        }
-    }
-    input_devices.extend(events.into_iter().filter_map(|event| {
-        event.name.and_then(|name| {
-            let path = PathBuf::from("/dev/input/").join(name);
-            let mut device = InputDevice::try_from(path).ok()?;
-            if device.is_input_device(device_filter, ignore_filter, mouse) && device.grab() {
-                device.print();
-                Some(device.into())
-            } else {
-                None
-            }
-        })
-    }));
-    Ok(true)
+// Receive signals through select(2) instead of the default handlers
+fn signal_watcher() -> anyhow::Result<SignalFd> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGHUP);
+    mask.add(Signal::SIGINT);
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGUSR1);
+    mask.add(Signal::SIGUSR2);
+    mask.thread_block()?;
+    Ok(SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)?)
 }
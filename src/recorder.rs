@@ -0,0 +1,75 @@
+use crate::action::Action;
+use crate::device::InputDeviceInfo;
+use anyhow::Context;
+use evdev::InputEvent;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A line of --record. `xremap simulate` can replay Event lines.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    // InputEvent read from a device
+    Event {
+        time: f64,
+        device: String,
+        path: PathBuf,
+        event_type: u16,
+        code: u16,
+        value: i32,
+    },
+    // Action dispatched to the output device
+    Action {
+        time: f64,
+        action: String,
+    },
+}
+
+// Write input events and dispatched actions to a file as JSON lines
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn new(path: &Path) -> anyhow::Result<Recorder> {
+        let file = File::create(path).with_context(|| format!("Failed to create '{}'", path.display()))?;
+        Ok(Recorder {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record_events(&mut self, device: &InputDeviceInfo, events: &[InputEvent]) -> io::Result<()> {
+        for event in events {
+            self.write(&Record::Event {
+                time: unix_time(event.timestamp()),
+                device: device.name.to_string(),
+                path: device.path.to_path_buf(),
+                event_type: event.event_type().0,
+                code: event.code(),
+                value: event.value(),
+            })?;
+        }
+        self.writer.flush()
+    }
+
+    pub fn record_action(&mut self, action: &Action) -> io::Result<()> {
+        self.write(&Record::Action {
+            time: unix_time(SystemTime::now()),
+            action: format!("{:?}", action),
+        })?;
+        self.writer.flush()
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
@@ -0,0 +1,302 @@
+use crate::action::Action;
+use crate::client::static_client::StaticClient;
+use crate::client::WMClient;
+use crate::clock::ManualClock;
+use crate::config::key::parse_key;
+use crate::config::Config;
+use crate::device::InputDeviceInfo;
+use crate::event::{Event, KeyEvent, RelativeEvent};
+use crate::event_handler::EventHandler;
+use crate::recorder::Record;
+use anyhow::{anyhow, bail, Context};
+use clap::ValueEnum;
+use evdev::{EventType, InputEvent, Key, RelativeAxisType};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Pretend events come from this device
+const SIMULATED_DEVICE_PATH: &str = "/dev/input/event0";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulateOutput {
+    /// every Action returned by the event handler
+    Actions,
+    /// only the events sent to the output device, in the input format
+    Keys,
+}
+
+pub struct SimulateOptions {
+    pub application: Option<String>,
+    pub window: Option<String>,
+    pub device: String,
+    pub output: SimulateOutput,
+}
+
+// One line of the input
+enum SimulatedInput {
+    KeyEvent(Key, i32),
+    RelativeEvent(u16, i32),
+    Sleep(Duration),
+}
+
+// xremap simulate: Feed a textual event stream through EventHandler and print what would be dispatched.
+//
+// Each line is one of:
+//   KEY press|release|repeat  e.g. `CapsLock press`, `KEY_A 0`
+//   KEY                       a press and a release
+//   REL_AXIS value            e.g. `REL_WHEEL -1`
+//   sleep MILLIS              wait, e.g. for alone_timeout_millis or timeout_millis
+//   {"type":"event",...}      a line written by `xremap --record`
+//
+// Time passes only by `sleep` and between recorded events, on a ManualClock instead of in real time.
+pub fn simulate(
+    config: &Config,
+    input: impl BufRead,
+    output: &mut impl Write,
+    options: SimulateOptions,
+) -> anyhow::Result<()> {
+    let clock = ManualClock::new();
+    let client = StaticClient {
+        current_application: options.application,
+        current_window: options.window,
+        window_properties: Default::default(),
+    };
+    let mut handler = EventHandler::new(
+        Box::new(clock.timer()),
+        Box::new(clock.clone()),
+        &config.default_mode,
+        Duration::from_millis(config.keypress_delay_ms),
+        WMClient::new("static", Box::new(client)),
+    );
+    let device = || InputDeviceInfo::new(&options.device, Path::new(SIMULATED_DEVICE_PATH));
+    let mut handle = |handler: &mut EventHandler, events: Vec<Event>| -> anyhow::Result<()> {
+        let actions = handler
+            .on_events(&events, config)
+            .map_err(|e| anyhow!("Failed handling {events:?}:\n  {e:?}"))?;
+        for action in actions {
+            print_action(output, &action, options.output)?;
+        }
+        Ok(())
+    };
+    // Timestamp of the last replayed --record line
+    let mut last_record_time: Option<f64> = None;
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let context = || format!("line {}: '{}'", index + 1, line);
+        if line.trim_start().starts_with('{') {
+            let record: Record = serde_json::from_str(&line).with_context(context)?;
+            if let Record::Event {
+                time,
+                device,
+                path,
+                event_type,
+                code,
+                value,
+            } = record
+            {
+                if let Some(last_time) = last_record_time {
+                    let delay = Duration::from_secs_f64((time - last_time).max(0.0));
+                    if sleep(&clock, delay) {
+                        handle(&mut handler, vec![Event::OverrideTimeout])?;
+                    }
+                }
+                last_record_time = Some(time);
+                let device = InputDeviceInfo::new(&device, &path);
+                let event = InputEvent::new(EventType(event_type), code, value);
+                handle(&mut handler, vec![Event::new(device, event)])?;
+            }
+            continue;
+        }
+
+        for input in parse_line(&line).with_context(context)? {
+            let events = match input {
+                SimulatedInput::KeyEvent(key, value) => {
+                    vec![Event::KeyEvent(device(), KeyEvent::new_with(key.code(), value))]
+                }
+                SimulatedInput::RelativeEvent(code, value) => {
+                    vec![Event::RelativeEvent(device(), RelativeEvent::new_with(code, value))]
+                }
+                SimulatedInput::Sleep(duration) => {
+                    if !sleep(&clock, duration) {
+                        continue;
+                    }
+                    vec![Event::OverrideTimeout]
+                }
+            };
+            handle(&mut handler, events)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_line(line: &str) -> anyhow::Result<Vec<SimulatedInput>> {
+    let line = line.split('#').next().unwrap_or_default();
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let inputs = match tokens[..] {
+        [] => vec![],
+        ["sleep", millis] => vec![SimulatedInput::Sleep(Duration::from_millis(millis.parse()?))],
+        [name, value] if name.to_uppercase().starts_with("REL_") => {
+            let axis = RelativeAxisType::from_str(&name.to_uppercase())
+                .map_err(|_| anyhow!("unknown relative axis '{}'", name))?;
+            vec![SimulatedInput::RelativeEvent(axis.0, value.parse()?)]
+        }
+        [key] => {
+            let key = parse_key(key).map_err(|e| anyhow!("{}", e))?;
+            vec![
+                SimulatedInput::KeyEvent(key, PRESS),
+                SimulatedInput::KeyEvent(key, RELEASE),
+            ]
+        }
+        [key, value] => {
+            let key = parse_key(key).map_err(|e| anyhow!("{}", e))?;
+            let value = match value {
+                "press" | "1" => PRESS,
+                "release" | "0" => RELEASE,
+                "repeat" | "2" => REPEAT,
+                _ => bail!("unknown key event value '{}'", value),
+            };
+            vec![SimulatedInput::KeyEvent(key, value)]
+        }
+        _ => bail!("expected 'KEY [press|release|repeat]', 'REL_AXIS value', or 'sleep MILLIS'"),
+    };
+    Ok(inputs)
+}
+
+// Sleep and return whether the timer of nested remaps has fired meanwhile
+fn sleep(clock: &ManualClock, duration: Duration) -> bool {
+    clock.advance(duration);
+    clock.take_expired_timer()
+}
+
+fn print_action(output: &mut impl Write, action: &Action, format: SimulateOutput) -> std::io::Result<()> {
+    if format == SimulateOutput::Actions {
+        return writeln!(output, "{:?}", action);
+    }
+    match action {
+        Action::KeyEvent(event) => {
+            let value = match event.value() {
+                RELEASE => "release",
+                PRESS => "press",
+                _ => "repeat",
+            };
+            writeln!(output, "{:?} {}", event.key, value)
+        }
+        Action::RelativeEvent(event) => writeln!(output, "{:?} {}", RelativeAxisType(event.code), event.value),
+        Action::MouseMovementEventCollection(events) => {
+            for event in events {
+                writeln!(output, "{:?} {}", RelativeAxisType(event.code), event.value)?;
+            }
+            Ok(())
+        }
+        Action::Command(command) => writeln!(output, "# launch {:?}", command),
+        Action::InputEvent(_) | Action::Delay(_) => Ok(()),
+    }
+}
+
+// InputEvent#value
+const RELEASE: i32 = 0;
+const PRESS: i32 = 1;
+const REPEAT: i32 = 2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::keymap::build_keymap_table;
+    use indoc::indoc;
+
+    fn simulate_yaml(config_yaml: &str, input: &str, output: SimulateOutput) -> String {
+        let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
+        config.keymap_table = build_keymap_table(&config.keymap);
+        let options = SimulateOptions {
+            application: Some("Firefox".to_string()),
+            window: None,
+            device: "Some Device".to_string(),
+            output,
+        };
+        let mut result = vec![];
+        simulate(&config, input.as_bytes(), &mut result, options).unwrap();
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn test_simulate_keys() {
+        let config = indoc! {"
+        modmap:
+          - remap:
+              CapsLock: Ctrl_L
+        keymap:
+          - application:
+              only: Firefox
+            remap:
+              C-b: left
+        "};
+        let input = indoc! {"
+        # comments and blank lines are ignored
+
+        CapsLock press
+        b
+        CapsLock release
+        REL_WHEEL -1
+        "};
+        assert_eq!(
+            indoc! {"
+            KEY_LEFTCTRL press
+            KEY_LEFTCTRL release
+            KEY_LEFT press
+            KEY_LEFT release
+            KEY_LEFTCTRL press
+            KEY_B release
+            KEY_LEFTCTRL release
+            REL_WHEEL -1
+            "},
+            simulate_yaml(config, input, SimulateOutput::Keys)
+        );
+    }
+
+    #[test]
+    fn test_simulate_actions() {
+        let config = indoc! {"
+        keymap:
+          - remap:
+              a: { launch: [echo] }
+        "};
+        assert_eq!(
+            "Command([\"echo\"])\nKeyEvent(KeyEvent { key: KEY_A, value: Release })\n",
+            simulate_yaml(config, "a", SimulateOutput::Actions)
+        );
+    }
+
+    #[test]
+    fn test_simulate_record() {
+        let config = indoc! {r#"
+        keymap:
+          - device:
+              only: Keyboard
+            remap:
+              a: b
+        "#};
+        let input = indoc! {r#"
+        {"type":"event","time":1.0,"device":"Keyboard","path":"/dev/input/event3","event_type":1,"code":30,"value":1}
+        {"type":"action","time":1.0,"action":"KeyEvent(..)"}
+        {"type":"event","time":1.0,"device":"Keyboard","path":"/dev/input/event3","event_type":0,"code":0,"value":0}
+        {"type":"event","time":1.01,"device":"Keyboard","path":"/dev/input/event3","event_type":1,"code":30,"value":0}
+        "#};
+        assert_eq!("KEY_B press\nKEY_B release\nKEY_A release\n", simulate_yaml(config, input, SimulateOutput::Keys));
+    }
+
+    #[test]
+    fn test_simulate_invalid_line() {
+        let config: Config = serde_yaml::from_str("{}").unwrap();
+        let options = SimulateOptions {
+            application: None,
+            window: None,
+            device: "Some Device".to_string(),
+            output: SimulateOutput::Keys,
+        };
+        let error = simulate(&config, "a press\nfoo press\n".as_bytes(), &mut vec![], options).unwrap_err();
+        assert_eq!("line 2: 'foo press'", error.to_string());
+    }
+}
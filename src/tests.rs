@@ -1,42 +1,31 @@
+use evdev::BusType;
 use evdev::EventType;
 use evdev::InputEvent;
+use evdev::InputId;
 use evdev::Key;
 use indoc::indoc;
-use nix::sys::timerfd::{ClockId, TimerFd, TimerFlags};
-use std::path::Path;
+use std::cell::RefCell;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
 use std::time::Duration;
+use std::{env, fs, process};
 
-use crate::client::{Client, WMClient};
-use crate::device::InputDeviceInfo;
+use crate::client::static_client::StaticClient;
+use crate::client::{Client, WMClient, WindowProperties};
+use crate::clock::ManualClock;
+use crate::config::device::{Capability, DeviceMatcher};
+use crate::device::{device_links_in, DeviceSelection, InputDeviceInfo};
 use crate::{
     action::Action,
-    config::{keymap::build_keymap_table, Config},
+    config::{keymap::build_keymap_table, suspend::Suspend, Config},
     event::{Event, KeyEvent, KeyValue, RelativeEvent},
-    event_handler::EventHandler,
+    event_handler::{EventHandler, SuspendState},
 };
 
-struct StaticClient {
-    current_application: Option<String>,
-}
-
-impl Client for StaticClient {
-    fn supported(&mut self) -> bool {
-        true
-    }
-    fn current_window(&mut self) -> Option<String> {
-        None
-    }
-
-    fn current_application(&mut self) -> Option<String> {
-        self.current_application.clone()
-    }
-}
-
 fn get_input_device_info<'a>() -> InputDeviceInfo<'a> {
-    InputDeviceInfo {
-        name: "Some Device",
-        path: &Path::new("/dev/input/event0"),
-    }
+    InputDeviceInfo::new("Some Device", Path::new("/dev/input/event0"))
 }
 
 #[test]
@@ -188,13 +177,18 @@ fn test_mouse_movement_event_accumulation() {
 //
 fn test_cursor_behavior_1() {
     use crate::device::InputDevice;
-    use crate::device::{get_input_devices, output_device};
+    use crate::device::{get_input_devices, output_device, DeviceSelection};
     // Setup to be able to send events
-    let mut input_devices = match get_input_devices(&[String::from("/dev/input/event25")], &[], true, false) {
+    let selection = DeviceSelection {
+        include: vec![DeviceMatcher::Name(String::from("/dev/input/event25"))],
+        mouse: true,
+        ..Default::default()
+    };
+    let mut input_devices = match get_input_devices(&selection, false, true) {
         Ok(input_devices) => input_devices,
         Err(e) => panic!("Failed to prepare input devices: {}", e),
     };
-    let mut output_device = match output_device(input_devices.values().next().map(InputDevice::bus_type), true) {
+    let mut output_device = match output_device(input_devices.values().next().map(InputDevice::bus_type), true, None) {
         Ok(output_device) => output_device,
         Err(e) => panic!("Failed to prepare an output device: {}", e),
     };
@@ -228,13 +222,18 @@ fn test_cursor_behavior_1() {
 // Please refer to the comment above test_cursor_behavior_1 for information on how to run these tests.
 fn test_cursor_behavior_2() {
     use crate::device::InputDevice;
-    use crate::device::{get_input_devices, output_device};
+    use crate::device::{get_input_devices, output_device, DeviceSelection};
     // Setup to be able to send events
-    let mut input_devices = match get_input_devices(&[String::from("/dev/input/event25")], &[], true, false) {
+    let selection = DeviceSelection {
+        include: vec![DeviceMatcher::Name(String::from("/dev/input/event25"))],
+        mouse: true,
+        ..Default::default()
+    };
+    let mut input_devices = match get_input_devices(&selection, false, true) {
         Ok(input_devices) => input_devices,
         Err(e) => panic!("Failed to prepare input devices: {}", e),
     };
-    let mut output_device = match output_device(input_devices.values().next().map(InputDevice::bus_type), true) {
+    let mut output_device = match output_device(input_devices.values().next().map(InputDevice::bus_type), true, None) {
         Ok(output_device) => output_device,
         Err(e) => panic!("Failed to prepare an output device: {}", e),
     };
@@ -494,10 +493,7 @@ fn test_device_override() {
     assert_actions(
         config,
         vec![Event::KeyEvent(
-            InputDeviceInfo {
-                name: "Some Device",
-                path: &Path::new("/dev/input/event0"),
-            },
+            InputDeviceInfo::new("Some Device", Path::new("/dev/input/event0")),
             KeyEvent::new(Key::KEY_A, KeyValue::Press),
         )],
         vec![
@@ -513,10 +509,7 @@ fn test_device_override() {
     assert_actions(
         config,
         vec![Event::KeyEvent(
-            InputDeviceInfo {
-                name: "Other Device",
-                path: &Path::new("/dev/input/event1"),
-            },
+            InputDeviceInfo::new("Other Device", Path::new("/dev/input/event1")),
             KeyEvent::new(Key::KEY_A, KeyValue::Press),
         )],
         vec![
@@ -530,6 +523,95 @@ fn test_device_override() {
     );
 }
 
+#[test]
+fn test_device_matcher() {
+    let device = InputDeviceInfo::new("Keychron K2 Keyboard", Path::new("/dev/input/event3"));
+    let matches = |filter: &str| device.matches(&DeviceMatcher::from_str(filter).unwrap());
+    assert!(matches("/dev/input/event3"));
+    assert!(matches("event3"));
+    assert!(matches("Keychron"));
+    assert!(!matches("keychron"));
+    assert!(matches("/keychron k\\d/i"));
+    assert!(matches("/event[0-9]$/"));
+    assert!(matches("glob:Keychron*Keyboard"));
+    assert!(!matches("glob:Keychron"));
+    assert!(matches("substring:K2"));
+
+    let device = InputDeviceInfo {
+        id: InputId::new(BusType::BUS_USB, 0x05ac, 0x024f, 0x0111),
+        phys: Some("usb-0000:00:14.0-2/input0"),
+        uniq: None,
+        capabilities: &[Capability::Keyboard],
+        ..device
+    };
+    let matches = |filter: &str| device.matches(&DeviceMatcher::from_str(filter).unwrap());
+    assert!(matches("id:05ac:024f"));
+    assert!(matches("id:05ac"));
+    assert!(!matches("id:05ac:0250"));
+    assert!(matches("bus:usb"));
+    assert!(!matches("bus:bluetooth"));
+    assert!(matches("phys:glob:usb-0000:00:14.0-*"));
+    assert!(!matches("uniq:glob:*"));
+    assert!(matches("has:keyboard"));
+    assert!(!matches("has:touchpad"));
+}
+
+#[test]
+fn test_device_matcher_link() {
+    let dir = TempDir::new("device_matcher_link");
+    let event3 = dir.path.join("event3");
+    let event4 = dir.path.join("event4");
+    fs::write(&event3, "").unwrap();
+    fs::write(&event4, "").unwrap();
+    let event3 = event3.canonicalize().unwrap();
+    let by_id = dir.path.join("by-id");
+    fs::create_dir(&by_id).unwrap();
+    let link = by_id.join("usb-Keychron_K2-event-kbd");
+    symlink(&event3, &link).unwrap();
+    symlink(&event4, by_id.join("usb-Logitech_MX-event-mouse")).unwrap();
+
+    let links = device_links_in(&[&by_id], &event3);
+    assert_eq!(vec![link.clone()], links);
+    let device = InputDeviceInfo {
+        links: &links,
+        ..InputDeviceInfo::new("Keychron K2 Keyboard", &event3)
+    };
+    let matches = |filter: &str| device.matches(&DeviceMatcher::from_str(filter).unwrap());
+    assert!(device.matches(&DeviceMatcher::Name(link.to_string_lossy().into_owned())));
+    assert!(matches("glob:*/usb-Keychron_K2-event-kbd"));
+    assert!(!matches("glob:*/usb-Logitech_MX-event-mouse"));
+}
+
+#[test]
+fn test_device_selection() {
+    let config = parse_config(indoc! {"
+        devices:
+          include: [Keychron, has:mouse]
+          ignore: bus:bluetooth
+          options:
+            - device: has:mouse
+              grab: false
+              output: mouse
+    "});
+    let keyboard = DeviceMatcher::Name(String::from("Keychron"));
+    let selection = DeviceSelection::new(&config.devices, &[], &[], None);
+    assert_eq!(vec![keyboard.clone(), DeviceMatcher::Capability(Capability::Mouse)], selection.include);
+    assert_eq!(vec![DeviceMatcher::Bus(BusType::BUS_BLUETOOTH)], selection.ignore);
+    assert_eq!(vec!["mouse"], selection.outputs());
+    assert!(!selection.options[0].grab);
+
+    // CLI flags override the config
+    let selection = DeviceSelection::new(&config.devices, std::slice::from_ref(&keyboard), &[], Some(true));
+    assert_eq!(vec![keyboard], selection.include);
+    assert_eq!(vec![DeviceMatcher::Bus(BusType::BUS_BLUETOOTH)], selection.ignore);
+    assert!(selection.mouse);
+
+    // including --mouse=false
+    let config = parse_config("devices: { mouse: true }");
+    assert!(DeviceSelection::new(&config.devices, &[], &[], None).mouse);
+    assert!(!DeviceSelection::new(&config.devices, &[], &[], Some(false)).mouse);
+}
+
 #[test]
 fn test_merge_remaps() {
     let config = indoc! {"
@@ -649,6 +731,660 @@ fn test_merge_remaps_with_override() {
     )
 }
 
+#[test]
+fn test_inspect_matched_entries() {
+    let config = parse_config(indoc! {"
+        modmap:
+          - name: Caps
+            remap:
+              CapsLock: Ctrl_L
+        keymap:
+          - remap:
+              C-a: b
+          - name: Emacs
+            remap:
+              C-b: left
+    "});
+    let mut event_handler = new_event_handler();
+    event_handler.inspect_matches();
+    let events = vec![
+        Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)),
+        Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+    ];
+    event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(vec!["modmap 'Caps'", "keymap 'Emacs'"], event_handler.take_matched_entries());
+
+    let events = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_A, KeyValue::Press),
+    )];
+    event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(vec!["keymap"], event_handler.take_matched_entries());
+}
+
+#[test]
+fn test_config_reload_resets_removed_mode() {
+    let config = parse_config(indoc! {"
+        keymap:
+          - remap:
+              i: { set_mode: insert }
+          - mode: insert
+            remap:
+              a: b
+    "});
+    let mut event_handler = new_event_handler();
+    let events = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_I, KeyValue::Press),
+    )];
+    event_handler.on_events(&events, &config).unwrap();
+
+    // insert mode survives a reload that still sets it
+    event_handler.on_config_reload(&config).unwrap();
+    let events = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_A, KeyValue::Press),
+    )];
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(
+        format!("{:?}", actions),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_B, KeyValue::Release)),
+                Action::Delay(Duration::from_nanos(0)),
+                Action::Delay(Duration::from_nanos(0)),
+            ]
+        )
+    );
+
+    // but falls back to default_mode when the new config doesn't have it
+    let config = parse_config(indoc! {"
+        keymap:
+          - mode: default
+            remap:
+              a: c
+    "});
+    event_handler.on_config_reload(&config).unwrap();
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(
+        format!("{:?}", actions),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Press)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_C, KeyValue::Release)),
+                Action::Delay(Duration::from_nanos(0)),
+                Action::Delay(Duration::from_nanos(0)),
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_config_reload_releases_multi_purpose_key() {
+    let config = parse_config(indoc! {"
+        modmap:
+          - remap:
+              CapsLock:
+                held: Ctrl_L
+                alone: Esc
+    "});
+    let mut event_handler = new_event_handler();
+    let events = vec![
+        Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_CAPSLOCK, KeyValue::Press)),
+        Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+    ];
+    event_handler.on_events(&events, &config).unwrap();
+
+    let actions = event_handler.on_config_reload(&parse_config("{}")).unwrap();
+    assert_eq!(
+        format!("{:?}", actions),
+        format!("{:?}", vec![Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release))])
+    );
+}
+
+#[test]
+fn test_suspend_passes_keys_through() {
+    let config = parse_config(indoc! {"
+        keymap:
+          - remap:
+              a: b
+              C-s: { suspend: true }
+              C-r: { resume: true }
+    "});
+    let mut event_handler = new_event_handler();
+    let key_event = |key, value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(key, value));
+    let events = vec![
+        key_event(Key::KEY_LEFTCTRL, KeyValue::Press),
+        key_event(Key::KEY_S, KeyValue::Press),
+    ];
+    event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(SuspendState::PassThrough, event_handler.suspend_state());
+
+    // Keys aren't remapped while suspended
+    let events = vec![
+        key_event(Key::KEY_S, KeyValue::Release),
+        key_event(Key::KEY_LEFTCTRL, KeyValue::Release),
+        key_event(Key::KEY_A, KeyValue::Press),
+    ];
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(
+        format!("{:?}", actions),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_S, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press)),
+            ]
+        )
+    );
+
+    // except for the resume key, which is swallowed
+    let events = vec![
+        key_event(Key::KEY_A, KeyValue::Release),
+        key_event(Key::KEY_LEFTCTRL, KeyValue::Press),
+        key_event(Key::KEY_R, KeyValue::Press),
+    ];
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(
+        format!("{:?}", actions),
+        format!(
+            "{:?}",
+            vec![
+                Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Release)),
+                Action::KeyEvent(KeyEvent::new(Key::KEY_LEFTCTRL, KeyValue::Press)),
+            ]
+        )
+    );
+    assert_eq!(SuspendState::Active, event_handler.suspend_state());
+}
+
+#[test]
+fn test_suspend_timeout() {
+    let config = parse_config(indoc! {"
+        keymap:
+          - remap:
+              a: b
+    "});
+    let mut event_handler = new_event_handler();
+    event_handler
+        .suspend(&Suspend {
+            ungrab: true,
+            timeout: Some(Duration::from_millis(0)),
+        })
+        .unwrap();
+    assert_eq!(SuspendState::Ungrabbed, event_handler.suspend_state());
+
+    // Events are left to the ungrabbed devices, and it resumes once no key is held
+    let events = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_A, KeyValue::Press),
+    )];
+    let actions = event_handler.on_events(&events, &config).unwrap();
+    assert!(actions.is_empty(), "{actions:?}");
+    assert_eq!(SuspendState::Ungrabbed, event_handler.suspend_state());
+    let events = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_A, KeyValue::Release),
+    )];
+    event_handler.on_events(&events, &config).unwrap();
+    assert_eq!(SuspendState::Active, event_handler.suspend_state());
+}
+
+#[test]
+fn test_suspend_timeout_on_clock() {
+    let config = parse_config(indoc! {"
+        keymap:
+          - remap:
+              a: b
+    "});
+    let clock = ManualClock::new();
+    let mut event_handler = new_event_handler_at(&clock);
+    let suspend = Suspend {
+        ungrab: true,
+        timeout: Some(Duration::from_secs(60)),
+    };
+    let key = |value| Event::KeyEvent(get_input_device_info(), KeyEvent::new(Key::KEY_A, value));
+
+    // The timer resumes it without any key event
+    event_handler.suspend(&suspend).unwrap();
+    clock.advance(Duration::from_millis(59_999));
+    assert!(!clock.take_expired_timer());
+    clock.advance(Duration::from_millis(1));
+    assert!(clock.take_expired_timer());
+    event_handler.on_events(&vec![Event::OverrideTimeout], &config).unwrap();
+    assert_eq!(SuspendState::Active, event_handler.suspend_state());
+
+    // A key held on the timeout holds it off until the release
+    event_handler.suspend(&suspend).unwrap();
+    event_handler.on_events(&vec![key(KeyValue::Press)], &config).unwrap();
+    clock.advance(Duration::from_secs(60));
+    assert!(clock.take_expired_timer());
+    event_handler.on_events(&vec![Event::OverrideTimeout], &config).unwrap();
+    assert_eq!(SuspendState::Ungrabbed, event_handler.suspend_state());
+    event_handler.on_events(&vec![key(KeyValue::Release)], &config).unwrap();
+    assert_eq!(SuspendState::Active, event_handler.suspend_state());
+}
+
+#[test]
+fn test_alone_timeout() {
+    let config = parse_config(indoc! {"
+        modmap:
+          - remap:
+              Space:
+                held: Shift_L
+                alone: Space
+                alone_timeout_millis: 500
+    "});
+    let clock = ManualClock::new();
+    let mut event_handler = new_event_handler_at(&clock);
+    let mut space = |value| {
+        let events = vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_SPACE, value),
+        )];
+        format!("{:?}", event_handler.on_events(&events, &config).unwrap())
+    };
+    let actions = |keys: Vec<(Key, KeyValue)>| {
+        let actions: Vec<Action> = keys
+            .into_iter()
+            .map(|(key, value)| Action::KeyEvent(KeyEvent::new(key, value)))
+            .collect();
+        format!("{:?}", actions)
+    };
+
+    // Released within alone_timeout
+    assert_eq!(actions(vec![]), space(KeyValue::Press));
+    clock.advance(Duration::from_millis(499));
+    assert_eq!(
+        actions(vec![(Key::KEY_SPACE, KeyValue::Press), (Key::KEY_SPACE, KeyValue::Release)]),
+        space(KeyValue::Release)
+    );
+
+    // Released after alone_timeout
+    space(KeyValue::Press);
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(
+        actions(vec![
+            (Key::KEY_LEFTSHIFT, KeyValue::Press),
+            (Key::KEY_LEFTSHIFT, KeyValue::Release)
+        ]),
+        space(KeyValue::Release)
+    );
+
+    // Repeated before and after alone_timeout
+    space(KeyValue::Press);
+    clock.advance(Duration::from_millis(499));
+    assert_eq!(actions(vec![]), space(KeyValue::Repeat));
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(actions(vec![(Key::KEY_LEFTSHIFT, KeyValue::Press)]), space(KeyValue::Repeat));
+    assert_eq!(actions(vec![(Key::KEY_LEFTSHIFT, KeyValue::Repeat)]), space(KeyValue::Repeat));
+    assert_eq!(actions(vec![(Key::KEY_LEFTSHIFT, KeyValue::Release)]), space(KeyValue::Release));
+}
+
+#[test]
+fn test_application_modes() {
+    let config = parse_config(indoc! {"
+        application_modes:
+          - only: Emacs
+            mode: emacs
+        keymap:
+          - mode: emacs
+            remap:
+              a: b
+          - mode: insert
+            remap:
+              a: c
+          - remap:
+              i: { set_mode: insert }
+    "});
+    let application = Rc::new(RefCell::new(String::from("Firefox")));
+    let mut event_handler = new_switching_event_handler(&application);
+    let press_a = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_A, KeyValue::Press),
+    )];
+    let press_i = vec![Event::KeyEvent(
+        get_input_device_info(),
+        KeyEvent::new(Key::KEY_I, KeyValue::Press),
+    )];
+    event_handler.on_events(&press_i, &config).unwrap();
+    let actions = event_handler.on_events(&press_a, &config).unwrap();
+    assert_eq!(format!("{:?}", remapped_to(Key::KEY_C)), format!("{:?}", actions));
+
+    // Focusing Emacs switches to its mode
+    *application.borrow_mut() = String::from("Emacs");
+    let actions = event_handler.on_events(&press_a, &config).unwrap();
+    assert_eq!(format!("{:?}", remapped_to(Key::KEY_B)), format!("{:?}", actions));
+
+    // and leaving it restores the previous mode
+    *application.borrow_mut() = String::from("Firefox");
+    let actions = event_handler.on_events(&press_a, &config).unwrap();
+    assert_eq!(format!("{:?}", remapped_to(Key::KEY_C)), format!("{:?}", actions));
+}
+
+#[test]
+fn test_mode_memory() {
+    let config = parse_config(indoc! {"
+        mode_memory: application
+        keymap:
+          - mode: insert
+            remap:
+              a: c
+          - remap:
+              i: { set_mode: insert }
+              m: { set_mark: true }
+              b: { with_mark: b }
+    "});
+    let application = Rc::new(RefCell::new(String::from("Firefox")));
+    let mut event_handler = new_switching_event_handler(&application);
+    let press = |key| {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(key, KeyValue::Press),
+        )]
+    };
+    let actions_of = |actions: Vec<Action>| format!("{:?}", actions);
+    let passed_a = actions_of(vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))]);
+    let remapped_to_c = actions_of(remapped_to(Key::KEY_C));
+
+    // Enter insert mode with the mark set in Firefox
+    event_handler.on_events(&press(Key::KEY_I), &config).unwrap();
+    event_handler.on_events(&press(Key::KEY_M), &config).unwrap();
+
+    // Emacs starts from default_mode
+    *application.borrow_mut() = String::from("Emacs");
+    let actions = event_handler.on_events(&press(Key::KEY_A), &config).unwrap();
+    assert_eq!(passed_a, actions_of(actions));
+
+    // and Firefox gets its mode and mark back
+    *application.borrow_mut() = String::from("Firefox");
+    let actions = event_handler.on_events(&press(Key::KEY_A), &config).unwrap();
+    assert_eq!(remapped_to_c, actions_of(actions));
+    let actions = event_handler.on_events(&press(Key::KEY_B), &config).unwrap();
+    assert!(
+        actions_of(actions).contains(&format!("{:?}", KeyEvent::new(Key::KEY_LEFTSHIFT, KeyValue::Press))),
+        "with_mark should press Shift"
+    );
+}
+
+#[test]
+fn test_mode_memory_capacity() {
+    let config = parse_config(indoc! {"
+        mode_memory: application
+        keymap:
+          - mode: insert
+            remap:
+              a: c
+          - remap:
+              i: { set_mode: insert }
+    "});
+    let application = Rc::new(RefCell::new(String::new()));
+    let mut event_handler = new_switching_event_handler(&application);
+    let mut press_in = |name: &str, key| {
+        *application.borrow_mut() = name.to_string();
+        let events = vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(key, KeyValue::Press),
+        )];
+        format!("{:?}", event_handler.on_events(&events, &config).unwrap())
+    };
+    let passed_a = format!("{:?}", vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))]);
+
+    press_in("First", Key::KEY_I);
+    press_in("Second", Key::KEY_I);
+    for i in 0..255 {
+        press_in(&format!("Application {i}"), Key::KEY_A);
+    }
+    // The least recently left one is forgotten
+    assert_ne!(passed_a, press_in("Second", Key::KEY_A));
+    assert_eq!(passed_a, press_in("First", Key::KEY_A));
+}
+
+#[test]
+fn test_window_properties() {
+    assert_actions_with_client(
+        indoc! {"
+            keymap:
+              - window_properties:
+                  executable: { only: foot }
+                  fullscreen: true
+                remap:
+                  a: b
+        "},
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application: None,
+                current_window: None,
+                window_properties: WindowProperties {
+                    executable: Some(String::from("/usr/bin/foot")),
+                    fullscreen: Some(true),
+                    ..Default::default()
+                },
+            }),
+        ),
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )],
+        remapped_to(Key::KEY_B),
+    );
+}
+
+#[test]
+fn test_unknown_window_property() {
+    let config = indoc! {"
+        keymap:
+          - window_properties:
+              workspace: { not: ['3'] }
+            remap:
+              a: b
+    "};
+    let on_workspace = |workspace: Option<&str>| {
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application: None,
+                current_window: None,
+                window_properties: WindowProperties {
+                    workspace: workspace.map(String::from),
+                    ..Default::default()
+                },
+            }),
+        )
+    };
+    let press_a = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]
+    };
+    assert_actions_with_client(config, on_workspace(Some("1")), press_a(), remapped_to(Key::KEY_B));
+    assert_actions_with_client(
+        config,
+        on_workspace(Some("3")),
+        press_a(),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+    // A client that doesn't report workspaces matches neither `only` nor `not`
+    assert_actions_with_client(
+        config,
+        on_workspace(None),
+        press_a(),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+}
+
+#[test]
+fn test_resource_name_and_class() {
+    let config = indoc! {"
+        keymap:
+          - window_properties:
+              resource_name: { only: konsole }
+            remap:
+              a: b
+          - when: { resource_class: /^org\\.kde\\./ }
+            remap:
+              a: c
+    "};
+    let konsole_named = |resource_name: &str| {
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application: Some(String::from("org.kde.konsole")),
+                current_window: None,
+                window_properties: WindowProperties {
+                    resource_name: Some(String::from(resource_name)),
+                    resource_class: Some(String::from("org.kde.konsole")),
+                    ..Default::default()
+                },
+            }),
+        )
+    };
+    let press_a = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]
+    };
+    assert_actions_with_client(config, konsole_named("konsole"), press_a(), remapped_to(Key::KEY_B));
+    assert_actions_with_client(config, konsole_named("yakuake"), press_a(), remapped_to(Key::KEY_C));
+}
+
+#[test]
+fn test_when_condition() {
+    let config = indoc! {"
+        keymap:
+          - when:
+              all:
+                - application: Firefox
+                - not: { window: /Google Docs/ }
+                - not: { mode: insert }
+            remap:
+              a: b
+    "};
+    let press_a = || {
+        vec![Event::KeyEvent(
+            get_input_device_info(),
+            KeyEvent::new(Key::KEY_A, KeyValue::Press),
+        )]
+    };
+    let firefox_in = |window: &str| {
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application: Some(String::from("Firefox")),
+                current_window: Some(String::from(window)),
+                window_properties: Default::default(),
+            }),
+        )
+    };
+    assert_actions_with_client(config, firefox_in("Example Domain"), press_a(), remapped_to(Key::KEY_B));
+    assert_actions_with_client(
+        config,
+        firefox_in("Untitled - Google Docs"),
+        press_a(),
+        vec![Action::KeyEvent(KeyEvent::new(Key::KEY_A, KeyValue::Press))],
+    );
+}
+
+fn new_switching_event_handler(application: &Rc<RefCell<String>>) -> EventHandler {
+    new_event_handler_on(
+        &ManualClock::new(),
+        WMClient::new(
+            "switching",
+            Box::new(SwitchingClient {
+                application: application.clone(),
+            }),
+        ),
+    )
+}
+
+// A client whose application can be switched while EventHandler owns it
+struct SwitchingClient {
+    application: Rc<RefCell<String>>,
+}
+
+impl Client for SwitchingClient {
+    fn supported(&mut self) -> bool {
+        true
+    }
+    fn current_window(&mut self) -> Option<String> {
+        None
+    }
+    fn current_application(&mut self) -> Option<String> {
+        Some(self.application.borrow().clone())
+    }
+}
+
+// A directory for files of a test, which is removed when it's dropped
+pub struct TempDir {
+    pub path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("xremap-test-{}-{name}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn parse_config(config_yaml: &str) -> Config {
+    let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
+    config.keymap_table = build_keymap_table(&config.keymap);
+    config
+}
+
+fn new_event_handler() -> EventHandler {
+    new_event_handler_at(&ManualClock::new())
+}
+
+fn new_event_handler_at(clock: &ManualClock) -> EventHandler {
+    new_event_handler_on(
+        clock,
+        WMClient::new(
+            "static",
+            Box::new(StaticClient {
+                current_application: None,
+                current_window: None,
+                window_properties: Default::default(),
+            }),
+        ),
+    )
+}
+
+// EventHandler on a ManualClock, which moves only when the test advances it
+fn new_event_handler_on(clock: &ManualClock, application_client: WMClient) -> EventHandler {
+    EventHandler::new(
+        Box::new(clock.timer()),
+        Box::new(clock.clone()),
+        "default",
+        Duration::from_micros(0),
+        application_client,
+    )
+}
+
+// The actions of a key press remapped to another key
+fn remapped_to(key: Key) -> Vec<Action> {
+    vec![
+        Action::KeyEvent(KeyEvent::new(key, KeyValue::Press)),
+        Action::KeyEvent(KeyEvent::new(key, KeyValue::Release)),
+        Action::Delay(Duration::from_nanos(0)),
+        Action::Delay(Duration::from_nanos(0)),
+    ]
+}
+
 fn assert_actions(config_yaml: &str, events: Vec<Event>, actions: Vec<Action>) {
     assert_actions_with_current_application(config_yaml, None, events, actions);
 }
@@ -659,15 +1395,26 @@ fn assert_actions_with_current_application(
     events: Vec<Event>,
     actions: Vec<Action>,
 ) {
-    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
+    let client = WMClient::new(
+        "static",
+        Box::new(StaticClient {
+            current_application,
+            current_window: None,
+            window_properties: Default::default(),
+        }),
+    );
+    assert_actions_with_client(config_yaml, client, events, actions);
+}
+
+fn assert_actions_with_client(
+    config_yaml: &str,
+    application_client: WMClient,
+    events: Vec<Event>,
+    actions: Vec<Action>,
+) {
     let mut config: Config = serde_yaml::from_str(config_yaml).unwrap();
     config.keymap_table = build_keymap_table(&config.keymap);
-    let mut event_handler = EventHandler::new(
-        timer,
-        "default",
-        Duration::from_micros(0),
-        WMClient::new("static", Box::new(StaticClient { current_application })),
-    );
+    let mut event_handler = new_event_handler_on(&ManualClock::new(), application_client);
     let mut actual: Vec<Action> = vec![];
 
     actual.append(&mut event_handler.on_events(&events, &config).unwrap());